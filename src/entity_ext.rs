@@ -12,7 +12,7 @@ pub trait AsEntityExt: AsEntity {
 			StorageGuard<
 				hobo::signal::Mutable<T>,
 				hobo::owning_ref::OwningRef<
-					std::cell::Ref<'static, Box<(dyn DynStorage + 'static)>>,
+					std::cell::Ref<'static, Box<dyn DynStorage + 'static>>,
 					Storage<hobo::signal::Mutable<T>>
 				>,
 			>,
@@ -27,7 +27,7 @@ pub trait AsEntityExt: AsEntity {
 			StorageGuard<
 				hobo::signal::Mutable<T>,
 				hobo::owning_ref::OwningRef<
-					std::cell::Ref<'static, Box<(dyn DynStorage + 'static)>>,
+					std::cell::Ref<'static, Box<dyn DynStorage + 'static>>,
 					Storage<hobo::signal::Mutable<T>>
 				>,
 			>,
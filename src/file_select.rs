@@ -1,10 +1,35 @@
+use std::{cell::Cell, rc::Rc, task::Waker, time::Duration};
 use hobo::{prelude::*, create as e};
-use super::document;
+use wasm_bindgen::convert::FromWasmAbi;
+use super::{document, closure_mut};
+use super::entity_ext::AsEntityExt;
 #[allow(unused_imports)] use super::honk;
 
+mod drop_zone;
+mod paste;
+mod save;
+mod stream;
+
+pub use drop_zone::FileDropZoneExt;
+pub use paste::{FilePasteExt, from_paste};
+pub use save::{save, save_blob, save_stream};
+pub use stream::{Progress, StreamedFile};
+
+/// Resolves to whatever files were picked, without loading them.
 struct FileSelect {
 	element: e::Input,
-	file_load_future: Option<std::pin::Pin<Box<wasm_bindgen_futures::JsFuture>>>,
+	cancel_grace_period: Duration,
+}
+
+// the latest poll's, the future may have moved to another task since the listeners were added
+struct SelectWaker(Option<Waker>);
+
+// how much longer than the grace period a picker with nothing picked waits for `change` or `cancel`, in browsers that may not fire `cancel`
+const CANCEL_FALLBACK: Duration = Duration::from_secs(5);
+
+thread_local! {
+	// a file picker fired `cancel`, so this browser can be left to report cancels on its own
+	static CANCEL_SEEN: Cell<bool> = const { Cell::new(false) };
 }
 
 #[derive(Default, Clone, PartialEq, Eq)]
@@ -13,14 +38,105 @@ enum TaskState {
 	FirstPoll,
 	WaitingForFileSelect,
 	Errored(FileError),
-	LoadFile,
+	Selected,
 }
 
 #[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
 pub enum FileError {
-	#[error("File size exceeded 2 MB.")] FileTooBig,
+	#[error("File is {size} bytes, over the limit of {max}.")] FileTooBig { size: u64, max: u64 },
+	#[error("File is {size} bytes, under the minimum of {min}.")] FileTooSmall { size: u64, min: u64 },
+	#[error("File '{name}' of type '{mime}' is not allowed.")] WrongType { name: String, mime: String },
+	#[error("Invalid file: '{0}'.")] Invalid(String),
 	#[error("File selection canceled.")] Canceled,
 	#[error("Failed to load file: '{0}'.")] JsFileLoadError(String),
+	#[error("Failed to save file: '{0}'.")] JsFileSaveError(String),
+}
+
+type Validator = dyn Fn(&web_sys::File) -> Result<(), String>;
+
+/// What files to let through, checked once they're selected.
+///
+/// Allows anything up to 2 MB by default. A `&str` converts into options with just the `accept` hint set.
+#[derive(Clone)]
+pub struct FileOptions {
+	accept: Option<String>,
+	max_size: Option<u64>,
+	min_size: Option<u64>,
+	mime_types: Vec<String>,
+	extensions: Vec<String>,
+	validator: Option<Rc<Validator>>,
+	cancel_grace_period: Duration,
+}
+
+impl Default for FileOptions {
+	fn default() -> Self {
+		Self {
+			accept: None,
+			max_size: Some(2_000_000),
+			min_size: None,
+			mime_types: Vec::new(),
+			extensions: Vec::new(),
+			validator: None,
+			cancel_grace_period: Duration::from_secs(1),
+		}
+	}
+}
+
+impl From<&str> for FileOptions {
+	fn from(accept: &str) -> Self { Self::default().accept(accept) }
+}
+
+impl FileOptions {
+	pub fn new() -> Self { Self::default() }
+
+	/// The `accept` attribute for the browser's file picker, only a hint to it.
+	/// Made up from `mime_types` and `extensions` if not set.
+	#[must_use] pub fn accept(mut self, x: impl Into<String>) -> Self { self.accept = Some(x.into()); self }
+	/// In bytes, `None` for no limit.
+	#[must_use] pub fn max_size(mut self, x: impl Into<Option<u64>>) -> Self { self.max_size = x.into(); self }
+	/// In bytes.
+	#[must_use] pub fn min_size(mut self, x: u64) -> Self { self.min_size = Some(x); self }
+	/// Such as `image/png` or `image/*`.
+	/// A file passes if it matches any of these or any of the `extensions`, anything passes if both are empty.
+	#[must_use] pub fn mime_types(mut self, x: impl IntoIterator<Item = impl Into<String>>) -> Self { self.mime_types = x.into_iter().map(Into::into).collect(); self }
+	/// Such as `png` or `.png`, case insensitive.
+	#[must_use] pub fn extensions(mut self, x: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+		self.extensions = x.into_iter().map(|x| x.as_ref().trim_start_matches('.').to_lowercase()).collect();
+		self
+	}
+	/// Runs after the other checks, the error ends up in `FileError::Invalid`.
+	#[must_use] pub fn validator(mut self, f: impl Fn(&web_sys::File) -> Result<(), String> + 'static) -> Self { self.validator = Some(Rc::new(f)); self }
+	/// How long after the page gets focus back the file picker looks for picked files itself in case `change` is late, 1 second by default.
+	/// Canceling is up to the picker's `cancel` event. Until one has been seen, in case the browser doesn't fire it for file pickers,
+	/// having nothing picked 5 seconds after that counts as canceled too.
+	#[must_use] pub fn cancel_grace_period(mut self, x: Duration) -> Self { self.cancel_grace_period = x; self }
+
+	fn accept_attr(&self) -> String {
+		if let Some(accept) = &self.accept { return accept.clone(); }
+		self.mime_types.iter().cloned().chain(self.extensions.iter().map(|x| format!(".{x}"))).collect::<Vec<_>>().join(",")
+	}
+
+	fn is_allowed_type(&self, file: &web_sys::File) -> bool {
+		if self.mime_types.is_empty() && self.extensions.is_empty() { return true; }
+
+		let mime = file.type_();
+		let mime_ok = self.mime_types.iter().any(|x| match x.strip_suffix("/*") {
+			Some(prefix) => mime.split_once('/').is_some_and(|(x, _)| x.eq_ignore_ascii_case(prefix)),
+			None => x.eq_ignore_ascii_case(&mime),
+		});
+		let name = file.name().to_lowercase();
+		let extension_ok = name.rsplit_once('.').is_some_and(|(_, ext)| self.extensions.iter().any(|x| x == ext));
+		mime_ok || extension_ok
+	}
+
+	fn check(&self, file: &web_sys::File) -> Result<(), FileError> {
+		let size = file.size() as u64;
+		if let Some(max) = self.max_size && size > max { return Err(FileError::FileTooBig { size, max }); }
+		if let Some(min) = self.min_size && size < min { return Err(FileError::FileTooSmall { size, min }); }
+		if !self.is_allowed_type(file) { return Err(FileError::WrongType { name: file.name(), mime: file.type_() }); }
+		if let Some(validator) = &self.validator { validator(file).map_err(FileError::Invalid)?; }
+		Ok(())
+	}
 }
 
 pub struct UserFile {
@@ -29,38 +145,46 @@ pub struct UserFile {
 }
 
 impl std::future::Future for FileSelect {
-	type Output = Result<UserFile, FileError>;
+	type Output = Result<Vec<web_sys::File>, FileError>;
 
-	fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+	fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
 		let input = self.element;
+		let grace_period = self.cancel_grace_period;
+		input.get_cmp_mut::<SelectWaker>().0 = Some(cx.waker().clone());
 		let task_state = input.get_cmp::<TaskState>().clone();
 
 		match task_state {
 			TaskState::FirstPoll => {
+				let target = input.get_cmp::<web_sys::EventTarget>().clone();
 				input
-					.on_change(#[clown::clown] |_| {
-						let Some(file) = input.get_cmp::<web_sys::HtmlInputElement>().files().unwrap().item(0) else { return; };
-
-						if file.size() > 2_000_000. {
-							*input.get_cmp_mut::<TaskState>() = TaskState::Errored(FileError::FileTooBig);
-						} else {
-							input.add_component(Some(file));
-							*input.get_cmp_mut::<TaskState>() = TaskState::LoadFile;
-						}
-
-						honk!(cx.waker()).wake_by_ref();
+					.on_change(move |_| {
+						let Some(files) = input.get_cmp::<web_sys::HtmlInputElement>().files() else { return; };
+						FileSelect::settle(input, TaskState::Selected, file_list(&files));
 					})
-					.add_component(document().on_focus(#[clown::clown] |_| {
-						let waker = honk!(cx.waker()).clone();
+					.bundle(Listener::new(&target, "cancel", move |_: web_sys::Event| {
+						CANCEL_SEEN.set(true);
+						FileSelect::settle(input, TaskState::Errored(FileError::Canceled), Vec::new());
+					}))
+					// some browsers don't fire `cancel` for file inputs, so closing the picker is also guessed from the page getting focus back
+					// there's no telling whether they do, `oncancel` is there for dialogs either way
+					.add_component(document().on_focus(move |_| {
 						input.spawn(async move {
-							async_timer::interval(std::time::Duration::from_secs(1)).wait().await;
-
-							// Check this state instead of checking for file to avoid using the dom
-							let mut task_state = input.get_cmp_mut::<TaskState>();
-							if *task_state == TaskState::WaitingForFileSelect {
-								*task_state = TaskState::Errored(FileError::Canceled);
-								waker.wake_by_ref();
-							};
+							let picked = || input.get_cmp::<web_sys::HtmlInputElement>().files().map(|x| file_list(&x)).unwrap_or_default();
+
+							// picked files may well be there without a `change` yet
+							async_timer::interval(grace_period).wait().await;
+							let files = picked();
+							if !files.is_empty() { FileSelect::settle(input, TaskState::Selected, files); return; }
+							if CANCEL_SEEN.get() { return; }
+
+							// a `change` that's slow to come still gets to settle it first
+							async_timer::interval(CANCEL_FALLBACK).wait().await;
+							let files = picked();
+							if files.is_empty() {
+								FileSelect::settle(input, TaskState::Errored(FileError::Canceled), files);
+							} else {
+								FileSelect::settle(input, TaskState::Selected, files);
+							}
 						});
 					}));
 
@@ -69,23 +193,7 @@ impl std::future::Future for FileSelect {
 			},
 			TaskState::WaitingForFileSelect => return std::task::Poll::Pending,
 			TaskState::Errored(e) => return std::task::Poll::Ready(Err(e)),
-			TaskState::LoadFile => {
-				let mut file = input.get_cmp_mut::<Option<web_sys::File>>();
-
-				let future = if let Some(x) = self.file_load_future.as_mut() { x } else {
-					let promise = (*file).as_mut().unwrap().array_buffer();
-					let future = Box::pin(wasm_bindgen_futures::JsFuture::from(promise));
-					self.file_load_future = Some(future);
-					self.file_load_future.as_mut().unwrap()
-				};
-
-				return future.as_mut().poll(cx)
-					.map_ok(|js_val| UserFile {
-						js_object: file.take().unwrap(),
-						bytes: js_sys::Uint8Array::new(&js_val).to_vec(),
-					})
-					.map_err(|js_err| FileError::JsFileLoadError(js_err.as_string().unwrap()));
-			},
+			TaskState::Selected => return std::task::Poll::Ready(Ok(std::mem::take(&mut *input.get_cmp_mut::<Vec<web_sys::File>>()))),
 		}
 
 		std::task::Poll::Pending
@@ -96,13 +204,110 @@ impl Drop for FileSelect {
 	fn drop(&mut self) { self.element.remove() }
 }
 
-pub async fn open(accept: &str) -> Result<UserFile, FileError> {
-	FileSelect {
-		element: e::input()
-			.type_file()
-			.attr(web_str::accept(), accept)
-			.component(TaskState::default())
-			.allow_no_parent(),
-		file_load_future: None,
-	}.await
+impl FileSelect {
+	fn new(options: &FileOptions) -> Self {
+		Self {
+			element: e::input()
+				.type_file()
+				.attr(web_str::accept(), options.accept_attr())
+				.component(TaskState::default())
+				.component(SelectWaker(None))
+				.component(Vec::<web_sys::File>::new())
+				.allow_no_parent(),
+			cancel_grace_period: options.cancel_grace_period,
+		}
+	}
+
+	/// Whichever of `change`, `cancel` or the focus guess comes first decides.
+	fn settle(input: e::Input, state: TaskState, files: Vec<web_sys::File>) {
+		{
+			let mut task_state = input.get_cmp_mut::<TaskState>();
+			if *task_state != TaskState::WaitingForFileSelect { return; }
+			*task_state = state;
+		}
+		*input.get_cmp_mut::<Vec<web_sys::File>>() = files;
+		if let Some(waker) = &input.get_cmp::<SelectWaker>().0 { waker.wake_by_ref(); }
+	}
+
+	#[must_use] fn multiple(self) -> Self { self.element.set_attr(web_str::multiple(), ""); self }
+	#[must_use] fn directory(self) -> Self { self.element.set_attr("webkitdirectory", ""); self }
+}
+
+/// An event listener that's removed when dropped, for events hobo doesn't have handlers for.
+struct Listener<E: FromWasmAbi + 'static> {
+	target: web_sys::EventTarget,
+	name: &'static str,
+	cb: Closure<dyn FnMut(E)>,
+}
+
+impl<E: FromWasmAbi + 'static> Listener<E> {
+	fn new(target: &web_sys::EventTarget, name: &'static str, f: impl FnMut(E) + 'static) -> Self {
+		let cb = closure_mut(f);
+		target.add_event_listener_with_callback(name, cb.as_ref().unchecked_ref()).expect("can't add event listener");
+		Self { target: target.clone(), name, cb }
+	}
+}
+
+impl<E: FromWasmAbi + 'static> Drop for Listener<E> {
+	fn drop(&mut self) { self.target.remove_event_listener_with_callback(self.name, self.cb.as_ref().unchecked_ref()).ok(); }
+}
+
+fn file_list(files: &web_sys::FileList) -> Vec<web_sys::File> { (0..files.length()).filter_map(|i| files.item(i)).collect() }
+
+fn load_error(e: JsValue) -> FileError { FileError::JsFileLoadError(e.as_string().unwrap_or_else(|| format!("{e:?}"))) }
+
+async fn load(file: web_sys::File) -> Result<UserFile, FileError> {
+	let buffer = wasm_bindgen_futures::JsFuture::from(file.array_buffer()).await
+		.map_err(load_error)?;
+	Ok(UserFile { js_object: file, bytes: js_sys::Uint8Array::new(&buffer).to_vec() })
+}
+
+async fn check_and_load(file: web_sys::File, options: &FileOptions) -> Result<UserFile, FileError> {
+	options.check(&file)?;
+	load(file).await
+}
+
+// one after another, so there's only ever one read in flight no matter how many files were picked
+async fn check_and_load_all(files: Vec<web_sys::File>, options: &FileOptions) -> Vec<Result<UserFile, FileError>> {
+	let mut loaded = Vec::with_capacity(files.len());
+	for file in files { loaded.push(check_and_load(file, options).await); }
+	loaded
+}
+
+/// Pick a file, checked against `options` and loaded.
+///
+/// Dropping the future at any point is fine: the hidden input and its listeners go away and a read in progress is discarded.
+pub async fn open(options: impl Into<FileOptions>) -> Result<UserFile, FileError> {
+	let options = options.into();
+	let file = FileSelect::new(&options).await?.into_iter().next().ok_or(FileError::Canceled)?;
+	check_and_load(file, &options).await
+}
+
+/// Like [`open`], but the file is read as it's streamed rather than up front.
+pub async fn open_streamed(options: impl Into<FileOptions>) -> Result<StreamedFile, FileError> {
+	let options = options.into();
+	let file = FileSelect::new(&options).await?.into_iter().next().ok_or(FileError::Canceled)?;
+	options.check(&file)?;
+	Ok(StreamedFile::new(file))
+}
+
+/// Pick any number of files, each of them checked and loaded on its own so one bad file doesn't fail the rest.
+pub async fn open_many(options: impl Into<FileOptions>) -> Result<Vec<Result<UserFile, FileError>>, FileError> {
+	let options = options.into();
+	let files = FileSelect::new(&options).multiple().await?;
+	Ok(check_and_load_all(files, &options).await)
+}
+
+/// Pick a directory, getting every file in it and its subdirectories along with its path relative to the picked directory's parent,
+/// e.g. `photos/2024/cat.jpg`.
+pub async fn open_directory(options: impl Into<FileOptions>) -> Result<Vec<(String, Result<UserFile, FileError>)>, FileError> {
+	let options = options.into();
+	let files = FileSelect::new(&options).directory().await?;
+	let mut loaded = Vec::with_capacity(files.len());
+	for file in files {
+		// not in web-sys
+		let path = js_sys::Reflect::get(&file, &"webkitRelativePath".into()).ok().and_then(|x| x.as_string()).unwrap_or_else(|| file.name());
+		loaded.push((path, check_and_load(file, &options).await));
+	}
+	Ok(loaded)
 }
@@ -0,0 +1,79 @@
+use std::{cell::RefCell, rc::Rc};
+use hobo::prelude::*;
+use crate::entity_ext::AsEntityExt;
+use super::{FileError, FileOptions, Listener, UserFile, check_and_load_all, file_list};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct DraggingOver(bool);
+type DraggingOverState = hobo::signal::Mutable<DraggingOver>;
+
+// dragenter and dragleave fire for every child the drag crosses, so only the count of entered elements says whether it's still over the zone
+#[derive(Default)]
+struct DragDepth(u32);
+
+fn carries_files(e: &web_sys::DragEvent) -> bool {
+	e.data_transfer().is_some_and(|x| x.types().iter().any(|x| x.as_string().as_deref() == Some("Files")))
+}
+
+pub trait FileDropZoneExt: AsElement + Copy + 'static {
+	/// Accepts files dragged onto the element, checking and loading them the same way as [`open_many`](super::open_many).
+	fn add_file_drop_zone(&self, options: impl Into<FileOptions>, on_files: impl FnMut(Vec<Result<UserFile, FileError>>) + 'static) {
+		let this = *self;
+		let options = options.into();
+		let on_files = Rc::new(RefCell::new(on_files));
+		this.add_component(DraggingOverState::new(DraggingOver(false)));
+		this.add_component(DragDepth(0));
+
+		// hobo doesn't have handlers for the drag and drop events other than `drag`
+		let listen = |name: &'static str, f: Box<dyn FnMut(web_sys::DragEvent)>| {
+			let target = this.get_cmp::<web_sys::EventTarget>().clone();
+			this.add_bundle(Listener::new(&target, name, f));
+		};
+
+		listen("dragenter", Box::new(move |e| {
+			if !carries_files(&e) { return; }
+			e.prevent_default();
+			this.get_cmp_mut::<DragDepth>().0 += 1;
+			this.get_cmp::<DraggingOverState>().set_neq(DraggingOver(true));
+		}));
+		// the default is to not allow dropping
+		listen("dragover", Box::new(move |e| {
+			if !carries_files(&e) { return; }
+			e.prevent_default();
+			if let Some(x) = e.data_transfer() { x.set_drop_effect("copy"); }
+		}));
+		listen("dragleave", Box::new(move |e| {
+			if !carries_files(&e) { return; }
+			let depth = { let mut depth = this.get_cmp_mut::<DragDepth>(); depth.0 = depth.0.saturating_sub(1); depth.0 };
+			if depth == 0 { this.get_cmp::<DraggingOverState>().set_neq(DraggingOver(false)); }
+		}));
+		listen("drop", Box::new(move |e| {
+			if !carries_files(&e) { return; }
+			// otherwise the browser opens the file
+			e.prevent_default();
+			this.get_cmp_mut::<DragDepth>().0 = 0;
+			this.get_cmp::<DraggingOverState>().set_neq(DraggingOver(false));
+
+			let Some(files) = e.data_transfer().and_then(|x| x.files()) else { return; };
+			let files = file_list(&files);
+			let options = options.clone();
+			let on_files = Rc::clone(&on_files);
+			this.spawn(async move {
+				let files = check_and_load_all(files, &options).await;
+				(on_files.borrow_mut())(files);
+			});
+		}));
+	}
+
+	#[must_use] fn file_drop_zone(self, options: impl Into<FileOptions>, on_files: impl FnMut(Vec<Result<UserFile, FileError>>) + 'static) -> Self { self.add_file_drop_zone(options, on_files); self }
+
+	/// Whether files are being dragged over the element, for styling it as a drop target.
+	/// This will panic at runtime if the element isn't a drop zone.
+	fn dragging_over_signal(&self) -> impl hobo::signal::Signal<Item = bool> + 'static {
+		self.get_cmp::<DraggingOverState>().signal_ref(|x| x.0)
+	}
+
+	fn is_dragging_over(&self) -> bool { self.try_get_cmp::<DraggingOverState>().is_some_and(|x| x.get().0) }
+}
+
+impl<T: AsElement + Copy + 'static> FileDropZoneExt for T {}
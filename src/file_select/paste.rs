@@ -0,0 +1,56 @@
+use std::{cell::RefCell, rc::Rc};
+use futures::future::LocalBoxFuture;
+use hobo::{prelude::*, dom_events::EventHandler};
+use crate::document;
+use super::{FileError, FileOptions, UserFile, check_and_load_all, file_list};
+
+type OnFiles = Rc<RefCell<dyn FnMut(Vec<Result<UserFile, FileError>>)>>;
+
+fn pasted_files(e: &web_sys::ClipboardEvent) -> Vec<web_sys::File> {
+	let Some(data) = e.clipboard_data() else { return Vec::new(); };
+	// `items` has images copied from a page or screenshot tool as well, `files` is all some browsers fill in
+	let items = data.items();
+	let files = (0..items.length())
+		.filter_map(|i| items.get(i))
+		.filter(|x| x.kind() == "file")
+		.filter_map(|x| x.get_as_file().ok().flatten())
+		.collect::<Vec<_>>();
+	if !files.is_empty() { return files; }
+	data.files().map(|x| file_list(&x)).unwrap_or_default()
+}
+
+/// Gets the pasted files if there are any, leaving pastes of anything else alone.
+fn on_paste(options: FileOptions, on_files: OnFiles, spawn: impl Fn(LocalBoxFuture<'static, ()>) + 'static) -> impl FnMut(web_sys::ClipboardEvent) + 'static {
+	move |e| {
+		let files = pasted_files(&e);
+		if files.is_empty() { return; }
+		e.prevent_default();
+
+		let options = options.clone();
+		let on_files = Rc::clone(&on_files);
+		spawn(Box::pin(async move {
+			let files = check_and_load_all(files, &options).await;
+			(on_files.borrow_mut())(files);
+		}));
+	}
+}
+
+/// Files pasted anywhere on the page, checked and loaded the same way as [`open_many`](super::open_many).
+///
+/// Listens for as long as the returned handler is kept around.
+#[must_use]
+pub fn from_paste(options: impl Into<FileOptions>, on_files: impl FnMut(Vec<Result<UserFile, FileError>>) + 'static) -> EventHandler {
+	document().on_paste(on_paste(options.into(), Rc::new(RefCell::new(on_files)), wasm_bindgen_futures::spawn_local))
+}
+
+pub trait FilePasteExt: AsElement + Copy + 'static {
+	/// Like [`from_paste`], but only for pastes into the element, e.g. a focused text area or `contenteditable`.
+	fn add_file_paste(&self, options: impl Into<FileOptions>, on_files: impl FnMut(Vec<Result<UserFile, FileError>>) + 'static) {
+		let this = *self;
+		self.add_on_paste(on_paste(options.into(), Rc::new(RefCell::new(on_files)), move |f| this.spawn(f)));
+	}
+
+	#[must_use] fn file_paste(self, options: impl Into<FileOptions>, on_files: impl FnMut(Vec<Result<UserFile, FileError>>) + 'static) -> Self { self.add_file_paste(options, on_files); self }
+}
+
+impl<T: AsElement + Copy + 'static> FilePasteExt for T {}
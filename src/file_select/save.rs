@@ -0,0 +1,47 @@
+use futures::{Stream, StreamExt};
+use hobo::{prelude::*, create as e};
+use wasm_bindgen_futures::js_sys;
+use super::FileError;
+
+// the download may not have started yet when `click` returns, and revoking the url early breaks it
+const REVOKE_AFTER: std::time::Duration = std::time::Duration::from_secs(40);
+
+fn save_error(e: JsValue) -> FileError { FileError::JsFileSaveError(e.as_string().unwrap_or_else(|| format!("{e:?}"))) }
+
+fn save_parts(parts: &js_sys::Array, filename: &str, mime: &str) -> Result<(), FileError> {
+	let options = web_sys::BlobPropertyBag::new();
+	options.set_type(mime);
+	let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(parts, &options).map_err(save_error)?;
+	save_blob(&blob, filename)
+}
+
+/// Have the browser download `blob` as `filename`.
+pub fn save_blob(blob: &web_sys::Blob, filename: &str) -> Result<(), FileError> {
+	let url = web_sys::Url::create_object_url_with_blob(blob).map_err(save_error)?;
+	let a = e::a()
+		.attr(web_str::href(), &url)
+		.attr(web_str::download(), filename)
+		.allow_no_parent();
+	a.get_cmp::<web_sys::HtmlElement>().click();
+	a.remove();
+
+	wasm_bindgen_futures::spawn_local(async move {
+		async_timer::interval(REVOKE_AFTER).wait().await;
+		web_sys::Url::revoke_object_url(&url).ok();
+	});
+	Ok(())
+}
+
+/// Have the browser download `bytes` as `filename`, e.g. for an export button.
+pub fn save(bytes: &[u8], filename: &str, mime: &str) -> Result<(), FileError> {
+	save_parts(&js_sys::Array::of1(&js_sys::Uint8Array::from(bytes)), filename, mime)
+}
+
+/// Like [`save`], but the file is put together from `chunks` as they come,
+/// each one copied out of wasm memory right away instead of collecting all of them into one buffer first.
+pub async fn save_stream(chunks: impl Stream<Item = impl AsRef<[u8]>>, filename: &str, mime: &str) -> Result<(), FileError> {
+	let parts = js_sys::Array::new();
+	let mut chunks = std::pin::pin!(chunks);
+	while let Some(chunk) = chunks.next().await { parts.push(&js_sys::Uint8Array::from(chunk.as_ref())); }
+	save_parts(&parts, filename, mime)
+}
@@ -0,0 +1,90 @@
+use std::{pin::Pin, task::{Context, Poll}};
+use wasm_bindgen_futures::{JsFuture, js_sys};
+use super::{FileError, load_error};
+
+/// How much of a [`StreamedFile`] has been read, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+	pub read: u64,
+	pub total: u64,
+}
+
+impl Progress {
+	/// From 0 to 1, 1 for empty files.
+	pub fn fraction(&self) -> f64 { if self.total == 0 { 1. } else { self.read as f64 / self.total as f64 } }
+	pub fn is_done(&self) -> bool { self.read >= self.total }
+}
+
+/// A file that's read a chunk at a time as a `Stream` of bytes instead of all at once like [`UserFile`](super::UserFile),
+/// for hashing, parsing or uploading large files without holding all of them in memory.
+pub struct StreamedFile {
+	pub js_object: web_sys::File,
+	chunk_size: u64,
+	offset: u64,
+	total: u64,
+	progress: hobo::signal::Mutable<Progress>,
+	pending: Option<JsFuture>,
+	failed: bool,
+}
+
+impl From<web_sys::File> for StreamedFile {
+	fn from(file: web_sys::File) -> Self { Self::new(file) }
+}
+
+impl StreamedFile {
+	/// Reads in chunks of 1 MiB by default.
+	pub fn new(file: web_sys::File) -> Self {
+		let total = file.size() as u64;
+		Self {
+			js_object: file,
+			chunk_size: 1024 * 1024,
+			offset: 0,
+			total,
+			progress: hobo::signal::Mutable::new(Progress { read: 0, total }),
+			pending: None,
+			failed: false,
+		}
+	}
+
+	#[must_use] pub fn chunk_size(mut self, x: u64) -> Self { self.chunk_size = x.max(1); self }
+
+	pub fn size(&self) -> u64 { self.total }
+	pub fn progress(&self) -> Progress { self.progress.get() }
+	pub fn progress_signal(&self) -> impl hobo::signal::Signal<Item = Progress> + 'static { self.progress.signal() }
+}
+
+impl futures::Stream for StreamedFile {
+	type Item = Result<Vec<u8>, FileError>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if self.failed { return Poll::Ready(None); }
+
+		let pending = match &mut self.pending {
+			Some(x) => x,
+			None => {
+				if self.offset >= self.total { return Poll::Ready(None); }
+				let end = (self.offset + self.chunk_size).min(self.total);
+				let chunk = match self.js_object.slice_with_f64_and_f64(self.offset as f64, end as f64) {
+					Ok(x) => x,
+					Err(e) => { self.failed = true; return Poll::Ready(Some(Err(load_error(e)))); },
+				};
+				self.pending.insert(JsFuture::from(chunk.array_buffer()))
+			},
+		};
+
+		let res = futures::ready!(Pin::new(pending).poll(cx));
+		self.pending = None;
+		match res {
+			Ok(buffer) => {
+				let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+				self.offset += bytes.len() as u64;
+				self.progress.lock_mut().read = self.offset;
+				Poll::Ready(Some(Ok(bytes)))
+			},
+			Err(e) => {
+				self.failed = true;
+				Poll::Ready(Some(Err(load_error(e))))
+			},
+		}
+	}
+}
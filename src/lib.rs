@@ -5,6 +5,7 @@ use hobo::prelude::*;
 pub use entity_ext::AsEntityExt;
 pub use element_ext::{children_diff::{ChildrenDiff, ChildrenDiffConfig, ChildrenDiffConfigBuilder, ChildrenDiffElementExt, ItemMapping}, AsElementExt, FontTag, Clicked};
 pub use html_ext::{AExt, Toggleable, ToggleableExt};
+pub use file_select::{FileDropZoneExt, FilePasteExt};
 pub use svg::xml_to_svg;
 pub use __svgs as svgs;
 
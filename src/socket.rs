@@ -1,109 +1,686 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
-use wasm_bindgen_futures::js_sys;
+use std::{cell::{Cell, RefCell}, rc::{Rc, Weak}, time::Duration};
+use futures::{FutureExt, future::LocalBoxFuture};
 use serde::{Serialize, de::DeserializeOwned};
 use hobo::prelude::*;
-#[allow(unused_imports)] use super::{honk, slip};
+use super::entity_ext::AsEntityExt;
 
-pub struct Socket<Out> {
-	ws: Rc<RefCell<web_sys::WebSocket>>,
-	// this should probably be bounded
-	message_buffer: Rc<RefCell<VecDeque<Out>>>,
+mod auth;
+mod channel;
+mod chunking;
+mod codec;
+mod error;
+mod fallback;
+mod handle;
+mod handshake;
+mod heartbeat;
+mod http;
+mod loopback;
+mod outbox;
+mod reconnect;
+mod rpc;
+mod shared;
+#[cfg(feature = "server")] mod server;
+mod state;
+mod stream;
+mod transport;
+mod websocket;
+
+pub use auth::Credentials;
+pub use channel::{Channel, ChannelFrame, MultiplexSocket, MultiplexSocketBuilder};
+pub use chunking::{Chunking, Compression};
+use chunking::Reassembly;
+pub use codec::{Codec, Postcard};
+#[cfg(feature = "json")] pub use codec::Json;
+#[cfg(feature = "cbor")] pub use codec::Cbor;
+pub use error::SocketError;
+pub use handle::{SocketDropped, SocketHandle, register, registered, unregister};
+pub use handshake::{Handshake, VersionMismatch};
+pub use fallback::{Fallback, FallbackConnection};
+pub use heartbeat::Heartbeat;
+pub use http::{HttpConnection, LongPoll, Sse};
+pub use loopback::{Loopback, LoopbackConnection, LoopbackPeer};
+pub use outbox::{OutboxFull, Overflow, Priority, SendOptions, SendStatus};
+use outbox::Outbox;
+pub use reconnect::{Backoff, ReconnectPolicy};
+pub use rpc::{RpcError, RpcRequest, RpcResponse, RpcSocket, RpcSocketBuilder};
+#[cfg(feature = "server")] pub use server::{IncomingClient, ServerConnection, ServerOptions, ServerSender, SocketServer};
+pub use shared::{SharedConnection, SharedWebSocket};
+pub use state::ConnectionState;
+pub use stream::SocketStream;
+pub use transport::{Connection, Frame, Transport, TransportEvents};
+pub use websocket::{WebSocket, WebSocketConnection};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn encode<C: Codec>(msg: &impl Serialize) -> Result<Vec<u8>, SocketError> { C::encode(msg).map_err(|e| SocketError::Encode(format!("{e:?}"))) }
+
+/// A websocket that reconnects on its own and buffers messages while it's not connected.
+///
+/// The connection closes once the `Socket` is dropped, see [`SocketHandle`] for a reference that doesn't own it.
+pub struct Socket<Out, C: Codec = Postcard, T: Transport = WebSocket> {
+	inner: Rc<Inner<Out, T>>,
+	_codec: std::marker::PhantomData<C>,
+}
+
+struct Inner<Out, T: Transport> {
+	transport: T,
+	url: String,
+	connection: RefCell<Option<T::Connection>>,
+	// bumped on every connect, events from connections that were since replaced are ignored
+	generation: Cell<u64>,
+	handlers: RefCell<Option<Handlers>>,
+	// whether the codec wants text frames
+	text: bool,
+	chunking: Option<Chunking>,
+	// chunks of the message currently coming in
+	reassembly: RefCell<Reassembly>,
+	outbox: RefCell<Outbox<Out>>,
+	reconnect_policy: ReconnectPolicy,
+	// consecutive failed attempts, reset on open unless auth was just turned down, then once something comes in
+	attempt: Cell<u32>,
+	// set by `close`, stops the socket from reconnecting
+	closed: Cell<bool>,
+	pending_reconnect: RefCell<Option<futures::future::AbortHandle>>,
+	state: hobo::signal::Mutable<ConnectionState>,
+	heartbeat: Option<Heartbeat>,
+	heartbeat_task: RefCell<Option<futures::future::AbortHandle>>,
+	// `Transport::now` of the last frame received
+	last_inbound: Cell<f64>,
+	ping_sent_at: Cell<Option<f64>>,
+	latency: hobo::signal::Mutable<Option<Duration>>,
+	protocol_version: Option<u32>,
+	// waiting for the server to answer the hello, the socket is not open until it does
+	handshaking: Cell<bool>,
+	version_mismatch: hobo::signal::Mutable<Option<VersionMismatch>>,
+	max_frame_size: Option<usize>,
+	on_error: Option<Rc<ErrorHandler>>,
+	credentials: Option<Rc<CredentialsProvider>>,
+	// encoded `Credentials::auth` for the connection being opened
+	auth: RefCell<Option<Vec<u8>>>,
+	auth_expired: Option<AuthExpired>,
+	// a connection was turned down for expired auth and nothing has come in over one since
+	auth_retried: Cell<bool>,
+}
+
+type ErrorHandler = RefCell<dyn FnMut(&SocketError)>;
+// url and encoded auth message
+type CredentialsProvider = RefCell<dyn FnMut() -> LocalBoxFuture<'static, anyhow::Result<(Option<String>, Option<Vec<u8>>)>>>;
+
+#[derive(Clone)]
+struct AuthExpired {
+	codes: Vec<u16>,
+	refresh: Rc<RefCell<dyn FnMut() -> LocalBoxFuture<'static, ()>>>,
+}
+
+#[derive(Clone)]
+struct Handlers {
+	on_open: Rc<RefCell<dyn FnMut()>>,
+	on_message: Rc<RefCell<dyn FnMut(Vec<u8>)>>,
+	on_close: Rc<RefCell<dyn FnMut(u16, String)>>,
+}
+
+pub struct SocketBuilder<Out, C: Codec = Postcard, T: Transport = WebSocket> {
+	url: String,
+	transport: T,
+	reconnect_policy: ReconnectPolicy,
+	outbox_capacity: usize,
+	outbox_overflow: Overflow,
+	heartbeat: Option<Heartbeat>,
+	protocol_version: Option<u32>,
+	max_frame_size: Option<usize>,
+	chunking: Option<Chunking>,
+	on_error: Option<Rc<ErrorHandler>>,
+	credentials: Option<Rc<CredentialsProvider>>,
+	auth_expired: Option<AuthExpired>,
+	_pd: std::marker::PhantomData<(Out, C)>,
 }
 
-unsafe impl<Out> Send for Socket<Out> {}
-unsafe impl<Out> Sync for Socket<Out> {}
+impl<Out: Serialize + 'static, C: Codec, T: Transport> SocketBuilder<Out, C, T> {
+	/// Connect through something other than the browser's `WebSocket`, e.g. a [`Loopback`] in tests.
+	pub fn transport<NewT: Transport>(self, transport: NewT) -> SocketBuilder<Out, C, NewT> {
+		SocketBuilder {
+			url: self.url,
+			transport,
+			reconnect_policy: self.reconnect_policy,
+			outbox_capacity: self.outbox_capacity,
+			outbox_overflow: self.outbox_overflow,
+			heartbeat: self.heartbeat,
+			protocol_version: self.protocol_version,
+			max_frame_size: self.max_frame_size,
+			chunking: self.chunking,
+			on_error: self.on_error,
+			credentials: self.credentials,
+			auth_expired: self.auth_expired,
+			_pd: std::marker::PhantomData,
+		}
+	}
 
-impl<Out: Serialize + 'static> Socket<Out> {
-	pub fn new<In: DeserializeOwned + 'static>(url: &str, on_open: fn(&Self), on_message: fn(&Self, In)) -> Self {
-		let ws = Rc::new(RefCell::new(web_sys::WebSocket::new(url).unwrap()));
-		let message_buffer = Rc::new(RefCell::new(VecDeque::new()));
-		let interval_secs = Rc::new(RefCell::new(std::time::Duration::from_secs(0)));
+	#[must_use] pub fn reconnect_policy(mut self, x: ReconnectPolicy) -> Self { self.reconnect_policy = x; self }
+	/// How many messages to hold on to while the socket is not open, 10 with `Overflow::DropOldest` unless set.
+	#[must_use] pub fn outbox(mut self, capacity: usize, overflow: Overflow) -> Self { self.outbox_capacity = capacity; self.outbox_overflow = overflow; self }
+	/// Ping the server periodically and force a reconnect if the connection goes silent for too long,
+	/// which catches half-open connections after e.g. the laptop goes to sleep.
+	/// Incoming messages that encode to nothing are mistaken for echoes then, see [`Heartbeat`].
+	#[must_use] pub fn heartbeat(mut self, x: impl Into<Option<Heartbeat>>) -> Self { self.heartbeat = x.into(); self }
+	/// Exchange a [`Handshake`] before anything else on every connection. The socket only counts as open once the server accepts `version`,
+	/// if it doesn't the socket closes for good and reports a [`VersionMismatch`] instead of reconnecting.
+	#[must_use] pub fn protocol_version(mut self, x: impl Into<Option<u32>>) -> Self { self.protocol_version = x.into(); self }
+	/// Frames over this many bytes are discarded without decoding and reported as [`SocketError::FrameTooBig`].
+	#[must_use] pub fn max_frame_size(mut self, x: impl Into<Option<usize>>) -> Self { self.max_frame_size = x.into(); self }
+	/// Split outgoing messages over several frames and reassemble incoming ones, optionally compressing them.
+	/// The server has to be set up to match.
+	#[must_use] pub fn chunking(mut self, x: impl Into<Option<Chunking>>) -> Self { self.chunking = x.into(); self }
+	/// Called with errors that happen in the background and so have no caller to return to, e.g. to forward decode errors to telemetry.
+	/// They are logged either way.
+	#[must_use] pub fn on_error(mut self, f: impl FnMut(&SocketError) + 'static) -> Self { self.on_error = Some(Rc::new(RefCell::new(f))); self }
+
+	/// Runs before every connection attempt, e.g. to put a token that may have expired since the last one into the url or an auth message.
+	/// Failing counts as a failed attempt.
+	#[must_use] pub fn credentials<F>(mut self, mut f: impl FnMut() -> F + 'static) -> Self where
+		F: Future<Output = anyhow::Result<Credentials<Out>>> + 'static,
+	{
+		self.credentials = Some(Rc::new(RefCell::new(move || {
+			let credentials = f();
+			async move {
+				let Credentials { url, auth } = credentials.await?;
+				Ok((url, auth.as_ref().map(encode::<C>).transpose()?))
+			}.boxed_local()
+		})));
+		self
+	}
 
-		let onopen = Closure::<dyn Fn(web_sys::Event)>::new(#[clown::clown] |_: web_sys::Event| {
-			let Some(ws) = slip!(Rc::downgrade(&ws)).upgrade() else { return; };
-			let Some(message_buffer) = slip!(Rc::downgrade(&message_buffer)).upgrade() else { return; };
-			let Some(interval_secs) = slip!(Rc::downgrade(&interval_secs)).upgrade() else { return; };
+	/// When the server closes the connection with one of `codes`, run `refresh` and reconnect right away instead of waiting out the reconnect delay.
+	/// Meant for getting a new token that the next run of [`credentials`](Self::credentials) then picks up.
+	/// Until something comes in over a connection, further rejections go through the usual delay, so a server that accepts the connection and then turns down its auth frame isn't hammered.
+	#[must_use] pub fn auth_expired<F>(mut self, codes: impl IntoIterator<Item = u16>, mut refresh: impl FnMut() -> F + 'static) -> Self where
+		F: Future<Output = ()> + 'static,
+	{
+		self.auth_expired = Some(AuthExpired { codes: codes.into_iter().collect(), refresh: Rc::new(RefCell::new(move || refresh().boxed_local())) });
+		self
+	}
+
+	pub fn build<In: DeserializeOwned + 'static>(
+		self,
+		on_open: impl FnMut(&Socket<Out, C, T>) + 'static,
+		on_message: impl FnMut(&Socket<Out, C, T>, In) + 'static,
+	) -> Socket<Out, C, T> {
+		self.build_with(on_open, on_message, |_| {})
+	}
 
-			let this = Self { ws: Rc::clone(&ws), message_buffer: Rc::clone(&message_buffer) };
+	/// Like `build`, but incoming messages are delivered through the returned stream instead of a callback.
+	/// Outgoing messages can go through `Socket`'s `Sink` impl.
+	pub fn build_stream<In: DeserializeOwned + 'static>(self) -> (Socket<Out, C, T>, SocketStream<In>) {
+		let (tx, rx) = futures::channel::mpsc::unbounded();
+		let socket = self.build(|_| {}, move |_, msg| { tx.unbounded_send(msg).ok(); });
+		(socket, SocketStream(rx))
+	}
+
+	fn build_with<In: DeserializeOwned + 'static>(
+		self,
+		mut on_open: impl FnMut(&Socket<Out, C, T>) + 'static,
+		mut on_message: impl FnMut(&Socket<Out, C, T>, In) + 'static,
+		mut on_close: impl FnMut(&Socket<Out, C, T>) + 'static,
+	) -> Socket<Out, C, T> {
+		let inner = Rc::new(Inner {
+			transport: self.transport,
+			url: self.url,
+			connection: RefCell::new(None),
+			generation: Cell::new(0),
+			handlers: RefCell::new(None),
+			text: C::TEXT,
+			chunking: self.chunking,
+			reassembly: RefCell::new(Reassembly::default()),
+			outbox: RefCell::new(Outbox::new(self.outbox_capacity, self.outbox_overflow)),
+			reconnect_policy: self.reconnect_policy,
+			attempt: Cell::new(0),
+			closed: Cell::new(false),
+			pending_reconnect: RefCell::new(None),
+			state: hobo::signal::Mutable::new(ConnectionState::default()),
+			heartbeat: self.heartbeat,
+			heartbeat_task: RefCell::new(None),
+			last_inbound: Cell::new(0.),
+			ping_sent_at: Cell::new(None),
+			latency: hobo::signal::Mutable::new(None),
+			protocol_version: self.protocol_version,
+			handshaking: Cell::new(false),
+			version_mismatch: hobo::signal::Mutable::new(None),
+			max_frame_size: self.max_frame_size,
+			on_error: self.on_error,
+			credentials: self.credentials,
+			auth: RefCell::new(None),
+			auth_expired: self.auth_expired,
+			auth_retried: Cell::new(false),
+		});
+
+		// after the handshake if there is one, right away otherwise
+		let open = Rc::new(RefCell::new(move |inner: Rc<Inner<Out, T>>| {
+			let auth = inner.auth.borrow_mut().take();
+			if let Some(auth) = auth && let Err(e) = inner.send_bytes(auth) {
+				inner.report(e);
+				inner.force_close(4000, "failed to send auth");
+				return;
+			}
+			inner.opened();
+			let this = Socket::<Out, C, T>::from_inner(inner);
 			on_open(&this);
 
-			let buffer = std::mem::take(&mut message_buffer.borrow_mut() as &mut VecDeque<_>);
-			for msg in buffer { this.send(msg).ok(); }
-
-			*interval_secs.borrow_mut() = std::time::Duration::from_secs(0);
-		}).into_js_value();
-		let onmessage = Closure::<dyn Fn(web_sys::MessageEvent)>::new(#[clown::clown] |e: web_sys::MessageEvent| {
-			let Some(ws) = slip!(Rc::downgrade(&ws)).upgrade() else { return; };
-			let Some(message_buffer) = slip!(Rc::downgrade(&message_buffer)).upgrade() else { return; };
-			let Some(interval_secs) = slip!(Rc::downgrade(&interval_secs)).upgrade() else { return; };
-
-			let u8_arr = js_sys::Uint8Array::new(&e.data());
-			let msg = match postcard::from_bytes::<In>(&u8_arr.to_vec()) {
-				Ok(x) => x,
-				Err(e) => { log::error!("Error deserializing server message: {e:?}"); return; },
-			};
-
-			let this = Self { ws: Rc::clone(&ws), message_buffer: Rc::clone(&message_buffer) };
-			on_message(&this, msg);
-
-			*interval_secs.borrow_mut() = std::time::Duration::from_secs(0);
-		}).into_js_value();
-		let onclose = Closure::<dyn Fn(web_sys::CloseEvent)>::new(#[clown::clown] |_: web_sys::CloseEvent| {
-			let ws = slip!(Rc::downgrade(&ws)).clone();
-			let mut interval_secs = interval_secs.borrow_mut();
-
-			*interval_secs = (*interval_secs + std::time::Duration::from_secs(15)).min(std::time::Duration::from_secs(60 * 10));
-			log::info!("waiting for {interval_secs:?} before reconnecting");
-			let mut interval = async_timer::interval(*interval_secs);
-			drop(interval_secs);
-			wasm_bindgen_futures::spawn_local(async move { loop {
-				// log::info!("socket closed, try again");
-				interval.wait().await;
-				let Some(ws) = ws.upgrade() else { break; };
-				let mut ws = ws.borrow_mut();
-				match web_sys::WebSocket::new(&ws.url()) {
-					Ok(new_ws) => {
-						new_ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-						new_ws.set_onopen(ws.onopen().as_ref());
-						new_ws.set_onmessage(ws.onmessage().as_ref());
-						new_ws.set_onclose(ws.onclose().as_ref());
-						*ws = new_ws;
-						break;
-					},
-					// this is very unlikely to happen
-					Err(e) => log::warn!("{e:?}"),
+			let buffer = this.inner.outbox.borrow_mut().drain();
+			for (msg, options) in buffer {
+				if let Err(e) = this.send_with(msg, options) { this.inner.report(e); }
+			}
+		}));
+
+		let weak = Rc::downgrade(&inner);
+		*inner.handlers.borrow_mut() = Some(Handlers {
+			on_open: Rc::new(RefCell::new({ let weak = Weak::clone(&weak); let open = Rc::clone(&open); move || {
+				let Some(inner) = weak.upgrade() else { return; };
+
+				match inner.protocol_version {
+					Some(version) => inner.send_hello(encode::<C>(&Handshake::Hello { version })),
+					None => (open.borrow_mut())(inner),
+				}
+			} })),
+			on_message: Rc::new(RefCell::new({ let weak = Weak::clone(&weak); move |bytes: Vec<u8>| {
+				let Some(inner) = weak.upgrade() else { return; };
+
+				if inner.received(&bytes) { return; }
+				if let Some(limit) = inner.max_frame_size && bytes.len() > limit {
+					inner.report(SocketError::FrameTooBig { size: bytes.len(), limit });
+					return;
+				}
+				let bytes = if let Some(chunking) = &inner.chunking {
+					let pushed = inner.reassembly.borrow_mut().push(&bytes, chunking);
+					match pushed {
+						Ok(Some(x)) => x,
+						Ok(None) => return,
+						Err(e) => { inner.report(e); return; },
+					}
+				} else { bytes };
+				if inner.handshaking.get() {
+					match C::decode::<Handshake>(&bytes) {
+						Ok(Handshake::Accepted) => {
+							inner.handshaking.set(false);
+							inner.version_mismatch.set(None);
+							(open.borrow_mut())(inner);
+						},
+						Ok(Handshake::Rejected { supported }) => inner.version_mismatched(supported),
+						Ok(x @ Handshake::Hello { .. }) => { inner.report(SocketError::Handshake(format!("unexpected {x:?} from server"))); inner.version_mismatched(Vec::new()); },
+						Err(e) => { inner.report(SocketError::Handshake(format!("{e:?}"))); inner.version_mismatched(Vec::new()); },
+					}
+					return;
 				}
-			} });
-		}).into_js_value();
-
-		{
-			let ws = ws.borrow_mut();
-			ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-			ws.set_onopen(Some(onopen.unchecked_ref()));
-			ws.set_onmessage(Some(onmessage.unchecked_ref()));
-			ws.set_onclose(Some(onclose.unchecked_ref()));
+				let msg = match C::decode::<In>(&bytes) {
+					Ok(x) => x,
+					Err(e) => { inner.report(SocketError::Decode(format!("{e:?}"))); return; },
+				};
+
+				let this = Socket::<Out, C, T>::from_inner(inner);
+				on_message(&this, msg);
+			} })),
+			on_close: Rc::new(RefCell::new(move |code, reason| {
+				let Some(inner) = weak.upgrade() else { return; };
+				inner.disconnected(code, reason);
+				on_close(&Socket::<Out, C, T>::from_inner(inner));
+			})),
+		});
+		inner.connect();
+
+		Socket::from_inner(inner)
+	}
+}
+
+impl<Out: 'static, T: Transport> Inner<Out, T> {
+	fn handlers_for(this: &Weak<Self>, generation: u64) -> Option<Handlers> {
+		let this = this.upgrade()?;
+		if this.generation.get() != generation { return None; }
+		this.handlers.borrow().clone()
+	}
+
+	/// Replaces the current connection, if any, with a new one.
+	fn connect(self: &Rc<Self>) {
+		let generation = self.generation.get() + 1;
+		self.generation.set(generation);
+		self.handshaking.set(false);
+		self.reassembly.borrow_mut().clear();
+		self.auth.borrow_mut().take();
+		let stale = self.connection.borrow_mut().take();
+		drop(stale);
+
+		let Some(credentials) = self.credentials.clone() else { self.connect_to(&self.url, generation); return; };
+		self.state.set(ConnectionState::Connecting { attempt: self.attempt.get() });
+		let credentials = (credentials.borrow_mut())();
+		let weak = Rc::downgrade(self);
+		self.transport.spawn(async move {
+			let credentials = credentials.await;
+			let Some(this) = weak.upgrade() else { return; };
+			// closed or reconnected in the meantime
+			if this.closed.get() || this.generation.get() != generation { return; }
+			match credentials {
+				Ok((url, auth)) => {
+					*this.auth.borrow_mut() = auth;
+					this.connect_to(url.as_deref().unwrap_or(&this.url), generation);
+				},
+				Err(e) => {
+					this.report(SocketError::Credentials(format!("{e:?}")));
+					this.schedule_reconnect(1006, format!("{e:?}"));
+				},
+			}
+		});
+	}
+
+	fn connect_to(self: &Rc<Self>, url: &str, generation: u64) {
+		let weak = Rc::downgrade(self);
+		let events = TransportEvents {
+			on_open: Rc::new({ let weak = Weak::clone(&weak); move || if let Some(handlers) = Self::handlers_for(&weak, generation) { (handlers.on_open.borrow_mut())() } }),
+			on_message: Rc::new({ let weak = Weak::clone(&weak); move |bytes| if let Some(handlers) = Self::handlers_for(&weak, generation) { (handlers.on_message.borrow_mut())(bytes) } }),
+			on_close: Rc::new(move |code, reason| if let Some(handlers) = Self::handlers_for(&weak, generation) { (handlers.on_close.borrow_mut())(code, reason) }),
+		};
+
+		match self.transport.connect(url, events) {
+			Ok(connection) => {
+				*self.connection.borrow_mut() = Some(connection);
+				self.state.set(ConnectionState::Connecting { attempt: self.attempt.get() });
+			},
+			Err(e) => {
+				self.report(SocketError::Connect(format!("{e:?}")));
+				self.connection.borrow_mut().take();
+				// 1006 is what browsers report for connections that failed without a close frame
+				self.schedule_reconnect(1006, format!("{e:?}"));
+			},
+		}
+	}
+
+	fn opened(self: &Rc<Self>) {
+		self.state.set(ConnectionState::Open);
+		// accepting the connection and then turning down its auth frame doesn't count as success
+		if !self.auth_retried.get() { self.attempt.set(0); }
+		self.last_inbound.set(self.transport.now());
+		self.start_heartbeat();
+	}
+
+	/// What goes over the wire for an encoded message, one frame unless it's chunked.
+	fn frames(&self, bytes: Vec<u8>) -> Result<Vec<Frame>, SocketError> {
+		Ok(match &self.chunking {
+			Some(chunking) => chunking.split(bytes).into_iter().map(Frame::Binary).collect(),
+			None if self.text => vec![Frame::Text(String::from_utf8(bytes).map_err(|e| SocketError::Encode(e.to_string()))?)],
+			None => vec![Frame::Binary(bytes)],
+		})
+	}
+
+	/// Sends an encoded message right away, bypassing the outbox.
+	fn send_bytes(&self, bytes: Vec<u8>) -> Result<(), SocketError> {
+		let frames = self.frames(bytes)?;
+		let connection = self.connection.borrow();
+		let Some(connection) = connection.as_ref() else { return Err(SocketError::Send("not connected".to_owned())); };
+		frames.into_iter().try_for_each(|x| connection.send(x)).map_err(|e| SocketError::Send(format!("{e:?}")))
+	}
+
+	fn send_hello(self: &Rc<Self>, hello: Result<Vec<u8>, SocketError>) {
+		let sent = hello.and_then(|x| self.send_bytes(x)).map_err(|e| SocketError::Handshake(e.to_string()));
+		if let Err(e) = sent {
+			self.report(e);
+			self.force_close(4000, "handshake failed");
+			return;
+		}
+
+		self.handshaking.set(true);
+		let generation = self.generation.get();
+		let weak = Rc::downgrade(self);
+		let timeout = self.transport.sleep(HANDSHAKE_TIMEOUT);
+		self.transport.spawn(async move {
+			timeout.await;
+			let Some(this) = weak.upgrade() else { return; };
+			if this.generation.get() != generation || !this.handshaking.get() { return; }
+			this.report(SocketError::Handshake(format!("no answer from server in {HANDSHAKE_TIMEOUT:?}")));
+			this.force_close(4000, "handshake timeout");
+		});
+	}
+
+	fn version_mismatched(self: &Rc<Self>, supported: Vec<u32>) {
+		let mismatch = VersionMismatch { client: self.protocol_version.unwrap_or_default(), server: supported };
+		self.handshaking.set(false);
+		self.version_mismatch.set(Some(mismatch.clone()));
+		self.report(mismatch.into());
+		self.close(4001, "protocol version mismatch");
+	}
+
+	fn report(&self, e: SocketError) {
+		log::error!("{e}");
+		// an `on_error` that causes another error doesn't hear about that one
+		if let Some(on_error) = &self.on_error && let Ok(mut on_error) = on_error.try_borrow_mut() { on_error(&e); }
+	}
+
+	/// Returns `true` for heartbeat echoes, which are not meant to be decoded.
+	fn received(&self, bytes: &[u8]) -> bool {
+		let now = self.transport.now();
+		self.last_inbound.set(now);
+		if !self.handshaking.get() {
+			self.attempt.set(0);
+			self.auth_retried.set(false);
 		}
+		if self.heartbeat.is_none() || !bytes.is_empty() { return false; }
+
+		if let Some(sent_at) = self.ping_sent_at.take() { self.latency.set(Some(Duration::from_secs_f64((now - sent_at).max(0.) / 1000.))); }
+		true
+	}
+
+	fn disconnected(self: &Rc<Self>, code: u16, reason: String) {
+		self.connection.borrow_mut().take();
+		self.stop_heartbeat();
+		if self.closed.get() {
+			self.state.set(ConnectionState::Closed { code, reason });
+		} else if let Some(auth_expired) = self.auth_expired.clone() && auth_expired.codes.contains(&code) {
+			self.refresh_auth(auth_expired, code, reason);
+		} else {
+			self.schedule_reconnect(code, reason);
+		}
+	}
+
+	fn refresh_auth(self: &Rc<Self>, auth_expired: AuthExpired, code: u16, reason: String) {
+		log::info!("socket auth expired, refreshing");
+		// a fresh token that's turned down right away again goes through the usual backoff
+		let retried = self.auth_retried.replace(true);
+		self.state.set(ConnectionState::Reconnecting { code, reason: reason.clone(), attempt: self.attempt.get(), retry_at: self.transport.now() });
+		let generation = self.generation.get();
+		let refresh = (auth_expired.refresh.borrow_mut())();
+		let weak = Rc::downgrade(self);
+		self.transport.spawn(async move {
+			refresh.await;
+			let Some(this) = weak.upgrade() else { return; };
+			if this.closed.get() || this.generation.get() != generation { return; }
+			if retried { this.schedule_reconnect(code, reason); } else { this.connect(); }
+		});
+	}
+
+	fn schedule_reconnect(self: &Rc<Self>, code: u16, reason: String) {
+		let attempt = self.attempt.get() + 1;
+		self.attempt.set(attempt);
+		let Some(delay) = self.reconnect_policy.delay(attempt, self.transport.random()) else {
+			log::info!("socket closed, not reconnecting after {} attempts", attempt - 1);
+			self.state.set(ConnectionState::Closed { code, reason });
+			return;
+		};
+
+		log::info!("waiting for {delay:?} before reconnecting");
+		let retry_at = self.transport.now() + delay.as_secs_f64() * 1000.;
+		self.state.set(ConnectionState::Reconnecting { code, reason, attempt, retry_at });
+		let (wait, handle) = futures::future::abortable(self.transport.sleep(delay));
+		if let Some(previous) = self.pending_reconnect.borrow_mut().replace(handle) { previous.abort(); }
+		let this = Rc::downgrade(self);
+		self.transport.spawn(async move {
+			if wait.await.is_err() { return; }
+			let Some(this) = this.upgrade() else { return; };
+			this.pending_reconnect.borrow_mut().take();
+			this.connect();
+		});
+	}
+
+	fn start_heartbeat(self: &Rc<Self>) {
+		let Some(heartbeat) = self.heartbeat else { return; };
+		let weak = Rc::downgrade(self);
+		let (task, handle) = futures::future::abortable(async move {
+			loop {
+				let Some(sleep) = weak.upgrade().map(|this| this.transport.sleep(heartbeat.interval)) else { return; };
+				sleep.await;
+				let Some(this) = weak.upgrade() else { return; };
+				let now = this.transport.now();
+				if now - this.last_inbound.get() > heartbeat.timeout.as_secs_f64() * 1000. {
+					this.report(SocketError::HeartbeatTimeout(heartbeat.timeout));
+					this.force_close(4000, "heartbeat timeout");
+					return;
+				}
+
+				this.ping_sent_at.set(Some(now));
+				let ping = if this.text { Frame::Text(String::new()) } else { Frame::Binary(Vec::new()) };
+				let sent = this.connection.borrow().as_ref().map(|x| x.send(ping));
+				if let Some(Err(e)) = sent { this.report(SocketError::Send(format!("ping: {e:?}"))); }
+			}
+		});
+		if let Some(previous) = self.heartbeat_task.borrow_mut().replace(handle) { previous.abort(); }
+		self.transport.spawn(async move { task.await.ok(); });
+	}
 
-		Self { ws, message_buffer }
+	fn stop_heartbeat(&self) {
+		if let Some(task) = self.heartbeat_task.borrow_mut().take() { task.abort(); }
+		self.ping_sent_at.set(None);
+		self.latency.set(None);
 	}
 
-	#[culpa::throws(anyhow::Error)]
-	pub fn send(&self, msg: Out) {
-		let ws = self.ws.borrow();
-		if ws.ready_state() != web_sys::WebSocket::OPEN {
-			log::warn!("failed to send, buffering: status is not web_sys::WebSocket::OPEN");
-			let mut message_buffer = self.message_buffer.borrow_mut();
-			message_buffer.push_back(msg);
-			if message_buffer.len() > 10 { message_buffer.pop_front(); }
+	/// Runs the regular close handling right away, without waiting for the transport to give up on the connection.
+	fn force_close(self: &Rc<Self>, code: u16, reason: &str) {
+		// whatever the old connection reports from now on is not ours to handle anymore
+		self.generation.set(self.generation.get() + 1);
+		if let Some(connection) = self.connection.borrow_mut().take() { connection.close(code, reason); }
+		let Some(handlers) = self.handlers.borrow().clone() else { return; };
+		(handlers.on_close.borrow_mut())(code, reason.to_owned());
+	}
+
+	fn close(&self, code: u16, reason: &str) {
+		self.closed.set(true);
+		if let Some(pending) = self.pending_reconnect.borrow_mut().take() { pending.abort(); }
+		self.stop_heartbeat();
+
+		// already disconnected, nothing else is going to report the close
+		let connection = self.connection.borrow();
+		let Some(connection) = connection.as_ref() else {
+			self.state.set(ConnectionState::Closed { code, reason: reason.to_owned() });
 			return;
+		};
+
+		self.state.set(ConnectionState::Closing);
+		connection.close(code, reason);
+	}
+}
+
+impl<Out, T: Transport> Drop for Inner<Out, T> {
+	fn drop(&mut self) {
+		if let Some(pending) = self.pending_reconnect.get_mut().take() { pending.abort(); }
+		if let Some(task) = self.heartbeat_task.get_mut().take() { task.abort(); }
+		if let Some(connection) = self.connection.get_mut().take() { connection.close(1000, ""); }
+	}
+}
+
+impl<Out: Serialize + 'static, C: Codec> Socket<Out, C> {
+	pub fn builder(url: &str) -> SocketBuilder<Out, C> {
+		SocketBuilder {
+			url: url.to_owned(),
+			transport: WebSocket,
+			reconnect_policy: ReconnectPolicy::default(),
+			outbox_capacity: 10,
+			outbox_overflow: Overflow::default(),
+			heartbeat: None,
+			protocol_version: None,
+			max_frame_size: None,
+			chunking: None,
+			on_error: None,
+			credentials: None,
+			auth_expired: None,
+			_pd: std::marker::PhantomData,
+		}
+	}
+
+	pub fn new<In: DeserializeOwned + 'static>(url: &str, on_open: impl FnMut(&Self) + 'static, on_message: impl FnMut(&Self, In) + 'static) -> Self {
+		Self::builder(url).build(on_open, on_message)
+	}
+}
+
+impl<Out: Serialize + 'static, C: Codec, T: Transport> Socket<Out, C, T> {
+	fn from_inner(inner: Rc<Inner<Out, T>>) -> Self { Self { inner, _codec: std::marker::PhantomData } }
+
+	pub fn state(&self) -> ConnectionState { self.inner.state.get_cloned() }
+
+	/// Time left until the next reconnect attempt, if one is scheduled.
+	pub fn retry_in(&self) -> Option<Duration> { self.inner.state.lock_ref().retry_in(self.inner.transport.now()) }
+
+	/// Tracks connection lifecycle, e.g. to show a "reconnecting in 12s…" banner or disable send buttons while offline.
+	pub fn state_signal(&self) -> impl hobo::signal::Signal<Item = ConnectionState> + 'static { self.inner.state.signal_cloned() }
+
+	/// Round-trip time of the last heartbeat, `None` until one comes back or if heartbeats are not enabled.
+	pub fn latency_signal(&self) -> impl hobo::signal::Signal<Item = Option<Duration>> + 'static { self.inner.latency.signal() }
+
+	/// Set when the server rejects the socket's protocol version, e.g. to prompt for a reload. Cleared by the next accepted handshake.
+	pub fn version_mismatch_signal(&self) -> impl hobo::signal::Signal<Item = Option<VersionMismatch>> + 'static { self.inner.version_mismatch.signal_cloned() }
+
+	/// Closes the connection and cancels any pending reconnect.
+	/// `code` must be either 1000 or in the 3000-4999 range.
+	///
+	/// The socket can be brought back with `reconnect_now`.
+	pub fn close(&self, code: u16, reason: &str) { self.inner.close(code, reason); }
+
+	/// Closes the socket once `entity` is removed, e.g. when the page that uses it is navigated away from.
+	pub fn bind_to(&self, entity: impl AsEntity) {
+		struct CloseOnDrop(Box<dyn Fn()>);
+		impl Drop for CloseOnDrop { fn drop(&mut self) { (self.0)() } }
+
+		let inner = Rc::downgrade(&self.inner);
+		entity.add_bundle(CloseOnDrop(Box::new(move || if let Some(inner) = inner.upgrade() { inner.close(1000, "owner removed"); })));
+	}
+
+	/// Skip whatever is left of the reconnect delay and try to connect right away,
+	/// e.g. when the browser reports that it's back online.
+	/// Also retries a socket that has given up according to its `ReconnectPolicy` or was closed with `close`.
+	///
+	/// Does nothing if the socket is already open or connecting.
+	pub fn reconnect_now(&self) {
+		if matches!(*self.inner.state.lock_ref(), ConnectionState::Open | ConnectionState::Connecting { .. }) { return; }
+		if let Some(pending) = self.inner.pending_reconnect.borrow_mut().take() { pending.abort(); }
+		self.inner.closed.set(false);
+		self.inner.connect();
+	}
+
+	pub fn send(&self, msg: Out) -> Result<SendStatus, SocketError> { self.send_with(msg, SendOptions::default()) }
+
+	/// Buffered messages are sent in order of priority once the socket opens, and so are messages the connection fails to send.
+	/// When the outbox is full and configured with `Overflow::Error`, the returned error is [`SocketError::OutboxFull`].
+	#[culpa::throws(SocketError)]
+	pub fn send_with(&self, msg: Out, options: SendOptions) -> SendStatus {
+		let connection = self.inner.connection.borrow();
+		// nothing but the handshake goes out until the server accepts it
+		let Some(connection) = connection.as_ref().filter(|x| x.is_open() && !self.inner.handshaking.get()) else {
+			log::warn!("failed to send, buffering: socket is not open");
+			return self.inner.outbox.borrow_mut().push(msg, options)?;
+		};
+		let sent = self.inner.frames(encode::<C>(&msg)?)?.into_iter().try_for_each(|frame| connection.send(frame));
+		if let Err(e) = sent {
+			self.inner.report(SocketError::Send(format!("{e:?}")));
+			return self.inner.outbox.borrow_mut().push(msg, options)?;
 		}
-		let send_res = ws.send_with_u8_array(&postcard::to_stdvec(&msg)?).map_err(|e| anyhow::anyhow!("{e:?}"));
-		if send_res.is_err() {
-			log::warn!("failed to send, buffering");
-			let mut message_buffer = self.message_buffer.borrow_mut();
-			message_buffer.push_back(msg);
-			if message_buffer.len() > 10 { message_buffer.pop_front(); }
+		SendStatus::Sent
+	}
+
+	/// Sends right away without ever going through the outbox, `false` if the connection is not open or failed to send.
+	#[culpa::throws(SocketError)]
+	fn send_now(&self, msg: &Out) -> bool {
+		let connection = self.inner.connection.borrow();
+		let Some(connection) = connection.as_ref().filter(|x| x.is_open() && !self.inner.handshaking.get()) else { return false; };
+		let sent = self.inner.frames(encode::<C>(msg)?)?.into_iter().try_for_each(|frame| connection.send(frame));
+		if let Err(e) = sent {
+			self.inner.report(SocketError::Send(format!("{e:?}")));
+			return false;
 		}
-		send_res?;
+		true
 	}
+
+	/// Number of messages waiting for the socket to open, e.g. to warn that changes have not been sent yet.
+	pub fn outbox_len_signal(&self) -> impl hobo::signal::Signal<Item = usize> + 'static { self.inner.outbox.borrow().len_signal() }
 }
@@ -0,0 +1,17 @@
+/// What to connect with, see [`SocketBuilder::credentials`](super::SocketBuilder::credentials).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials<Out> {
+	/// Replaces the url the socket was built with, e.g. to put a fresh token into its query.
+	pub url: Option<String>,
+	/// Sent before anything else once the connection opens, after the version handshake if there is one.
+	pub auth: Option<Out>,
+}
+
+impl<Out> Default for Credentials<Out> {
+	fn default() -> Self { Self { url: None, auth: None } }
+}
+
+impl<Out> Credentials<Out> {
+	#[must_use] pub fn url(mut self, x: impl Into<String>) -> Self { self.url = Some(x.into()); self }
+	#[must_use] pub fn auth(mut self, x: Out) -> Self { self.auth = Some(x); self }
+}
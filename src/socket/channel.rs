@@ -0,0 +1,133 @@
+use std::{cell::RefCell, collections::HashMap, rc::{Rc, Weak}};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use super::{Codec, Postcard, SendOptions, SendStatus, Socket, SocketBuilder, SocketError, SocketHandle, SocketStream, Transport, WebSocket};
+
+/// Envelope for everything that goes over a [`MultiplexSocket`], in both directions.
+///
+/// Payloads are encoded with the socket's codec on their own, so each channel can have its own message types.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelFrame {
+	/// Sent by the client when a channel is opened and again after every reconnect.
+	Subscribe(String),
+	/// Sent by the client when a channel is dropped.
+	Unsubscribe(String),
+	Message { channel: String, payload: Vec<u8> },
+}
+
+type ChannelHandler = RefCell<dyn FnMut(&[u8]) -> Result<(), SocketError>>;
+type ChannelHandlers = RefCell<HashMap<String, Rc<ChannelHandler>>>;
+
+/// Several independent typed channels over a single connection, see [`MultiplexSocket::channel`].
+pub struct MultiplexSocket<C: Codec = Postcard, T: Transport = WebSocket> {
+	socket: Socket<ChannelFrame, C, T>,
+	channels: Rc<ChannelHandlers>,
+}
+
+pub struct MultiplexSocketBuilder<C: Codec = Postcard, T: Transport = WebSocket> {
+	socket: SocketBuilder<ChannelFrame, C, T>,
+}
+
+impl<C: Codec, T: Transport> MultiplexSocketBuilder<C, T> {
+	/// Configure the underlying socket, e.g. its reconnect policy.
+	#[must_use] pub fn socket(mut self, f: impl FnOnce(SocketBuilder<ChannelFrame, C, T>) -> SocketBuilder<ChannelFrame, C, T>) -> Self { self.socket = f(self.socket); self }
+	pub fn transport<NewT: Transport>(self, transport: NewT) -> MultiplexSocketBuilder<C, NewT> { MultiplexSocketBuilder { socket: self.socket.transport(transport) } }
+
+	pub fn build(self) -> MultiplexSocket<C, T> {
+		let channels = Rc::new(ChannelHandlers::default());
+
+		let socket = self.socket.build_with(
+			{ let channels = Rc::downgrade(&channels); move |socket: &Socket<ChannelFrame, C, T>| {
+				let Some(channels) = channels.upgrade() else { return; };
+				for channel in channels.borrow().keys() { socket.send(ChannelFrame::Subscribe(channel.clone())).ok(); }
+			} },
+			{ let channels = Rc::downgrade(&channels); move |socket: &Socket<ChannelFrame, C, T>, frame: ChannelFrame| {
+				let Some(channels) = channels.upgrade() else { return; };
+				let ChannelFrame::Message { channel, payload } = frame else { log::warn!("unexpected frame from server: {frame:?}"); return; };
+				let Some(handler) = channels.borrow().get(&channel).cloned() else { log::warn!("message for unknown channel '{channel}'"); return; };
+				if let Err(e) = (handler.borrow_mut())(&payload) { socket.inner.report(e); }
+			} },
+			|_| {},
+		);
+
+		MultiplexSocket { socket, channels }
+	}
+}
+
+impl<C: Codec> MultiplexSocket<C> {
+	pub fn builder(url: &str) -> MultiplexSocketBuilder<C> { MultiplexSocketBuilder { socket: Socket::builder(url) } }
+	pub fn new(url: &str) -> Self { Self::builder(url).build() }
+}
+
+impl<C: Codec, T: Transport> MultiplexSocket<C, T> {
+	pub fn socket(&self) -> &Socket<ChannelFrame, C, T> { &self.socket }
+
+	/// Subscribes to `topic`, messages on it are passed to `on_message`.
+	/// The subscription lasts until the returned `Channel` is dropped.
+	///
+	/// Opening a channel for a topic that already has one replaces the previous handler.
+	pub fn channel<TopicIn, TopicOut>(&self, topic: &str, mut on_message: impl FnMut(TopicIn) + 'static) -> Channel<TopicOut, C, T> where
+		TopicIn: DeserializeOwned + 'static,
+		TopicOut: Serialize + 'static,
+	{
+		let handler: Rc<ChannelHandler> = Rc::new(RefCell::new(move |payload: &[u8]| {
+			on_message(C::decode::<TopicIn>(payload).map_err(|e| SocketError::Decode(format!("{e:?}")))?);
+			Ok(())
+		}));
+		let weak_handler = Rc::downgrade(&handler);
+		if self.channels.borrow_mut().insert(topic.to_owned(), handler).is_some() { log::warn!("channel '{topic}' opened twice, replacing previous handler"); }
+		// otherwise it's sent on open along with all the others
+		if self.socket.state().is_open() { self.socket.send(ChannelFrame::Subscribe(topic.to_owned())).ok(); }
+
+		Channel {
+			topic: topic.to_owned(),
+			socket: self.socket.handle(),
+			channels: Rc::downgrade(&self.channels),
+			handler: weak_handler,
+			_pd: std::marker::PhantomData,
+		}
+	}
+
+	/// Like `channel`, but messages are delivered through the returned stream.
+	pub fn channel_stream<TopicIn, TopicOut>(&self, topic: &str) -> (Channel<TopicOut, C, T>, SocketStream<TopicIn>) where
+		TopicIn: DeserializeOwned + 'static,
+		TopicOut: Serialize + 'static,
+	{
+		let (tx, rx) = futures::channel::mpsc::unbounded();
+		let channel = self.channel(topic, move |msg| { tx.unbounded_send(msg).ok(); });
+		(channel, SocketStream(rx))
+	}
+}
+
+/// Typed sender for one topic of a [`MultiplexSocket`], unsubscribes when dropped.
+pub struct Channel<Out, C: Codec = Postcard, T: Transport = WebSocket> {
+	topic: String,
+	socket: SocketHandle<ChannelFrame, C, T>,
+	channels: Weak<ChannelHandlers>,
+	// to tell whether the topic was taken over by another `Channel` since
+	handler: Weak<ChannelHandler>,
+	_pd: std::marker::PhantomData<Out>,
+}
+
+impl<Out: Serialize + 'static, C: Codec, T: Transport> Channel<Out, C, T> {
+	pub fn topic(&self) -> &str { &self.topic }
+
+	pub fn send(&self, msg: Out) -> Result<SendStatus, SocketError> { self.send_with(msg, SendOptions::default()) }
+
+	pub fn send_with(&self, msg: Out, options: SendOptions) -> Result<SendStatus, SocketError> {
+		let socket = self.socket.upgrade().ok_or(super::SocketDropped)?;
+		let payload = C::encode(&msg).map_err(|e| SocketError::Encode(format!("{e:?}")))?;
+		socket.send_with(ChannelFrame::Message { channel: self.topic.clone(), payload }, options)
+	}
+}
+
+impl<Out, C: Codec, T: Transport> Drop for Channel<Out, C, T> {
+	fn drop(&mut self) {
+		let Some(channels) = self.channels.upgrade() else { return; };
+		let mut channels = channels.borrow_mut();
+		if !channels.get(&self.topic).is_some_and(|x| Weak::ptr_eq(&Rc::downgrade(x), &self.handler)) { return; }
+		channels.remove(&self.topic);
+		drop(channels);
+		let Some(socket) = self.socket.upgrade() else { return; };
+		if socket.state().is_open() { socket.send(ChannelFrame::Unsubscribe(std::mem::take(&mut self.topic))).ok(); }
+	}
+}
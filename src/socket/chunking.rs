@@ -0,0 +1,196 @@
+use super::SocketError;
+
+/// Splits messages that don't fit into one frame and optionally compresses them, see [`SocketBuilder::chunking`](super::SocketBuilder::chunking).
+///
+/// Both ends have to enable chunking. Every frame then starts with a header byte saying whether more chunks of the same message follow
+/// and how the message is compressed, so each side compresses according to its own settings and the other side always knows how to read it.
+/// Chunked messages are always sent as binary frames, heartbeats stay empty frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+	/// Largest frame to send, header included.
+	pub max_frame_size: usize,
+	pub compression: Option<Compression>,
+	/// Messages over this many bytes, compressed or not, are discarded.
+	pub max_message_size: usize,
+}
+
+impl Default for Chunking {
+	fn default() -> Self { Self { max_frame_size: 64 * 1024, compression: None, max_message_size: 64 * 1024 * 1024 } }
+}
+
+impl Chunking {
+	pub fn new(max_frame_size: usize) -> Self { Self { max_frame_size, ..Self::default() } }
+	#[must_use] pub fn compression(mut self, x: impl Into<Option<Compression>>) -> Self { self.compression = x.into(); self }
+	#[must_use] pub fn max_message_size(mut self, x: usize) -> Self { self.max_message_size = x; self }
+
+	/// Frames for `bytes`, in the order they have to be sent.
+	pub(super) fn split(&self, bytes: Vec<u8>) -> Vec<Vec<u8>> {
+		let (compression, bytes) = match self.compression {
+			Some(x) => (x.id(), x.compress(&bytes)),
+			None => (0, bytes),
+		};
+
+		// room for the header
+		let chunk_size = self.max_frame_size.saturating_sub(1).max(1);
+		let chunks = if bytes.is_empty() { vec![&[][..]] } else { bytes.chunks(chunk_size).collect::<Vec<_>>() };
+		let last = chunks.len() - 1;
+		chunks.into_iter().enumerate().map(|(i, chunk)| {
+			let mut frame = Vec::with_capacity(chunk.len() + 1);
+			frame.push(compression | if i == last { FINAL } else { 0 });
+			frame.extend_from_slice(chunk);
+			frame
+		}).collect()
+	}
+}
+
+/// Each one is behind a feature of the same name, the receiving end needs it enabled too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+	#[cfg(feature = "deflate")] Deflate,
+	#[cfg(feature = "lz4")] Lz4,
+}
+
+impl Compression {
+	// what goes into the header, 0 is no compression
+	fn id(self) -> u8 {
+		match self {
+			#[cfg(feature = "deflate")] Self::Deflate => 1,
+			#[cfg(feature = "lz4")] Self::Lz4 => 2,
+		}
+	}
+
+	#[cfg_attr(not(any(feature = "deflate", feature = "lz4")), expect(unused_variables))]
+	fn compress(self, bytes: &[u8]) -> Vec<u8> {
+		match self {
+			#[cfg(feature = "deflate")] Self::Deflate => miniz_oxide::deflate::compress_to_vec(bytes, 6),
+			#[cfg(feature = "lz4")] Self::Lz4 => lz4_flex::compress_prepend_size(bytes),
+		}
+	}
+}
+
+// header byte layout: the low bits say how the message is compressed, `FINAL` marks its last chunk
+const COMPRESSION_MASK: u8 = 0b0111_1111;
+const FINAL: u8 = 0b1000_0000;
+
+#[cfg_attr(not(any(feature = "deflate", feature = "lz4")), expect(unused_variables))]
+fn decompress(compression: u8, bytes: Vec<u8>, limit: usize) -> Result<Vec<u8>, SocketError> {
+	match compression {
+		0 => Ok(bytes),
+		#[cfg(feature = "deflate")] 1 => miniz_oxide::inflate::decompress_to_vec_with_limit(&bytes, limit).map_err(|e| SocketError::Decode(format!("deflate: {e:?}"))),
+		#[cfg(feature = "lz4")] 2 => {
+			// the size is prepended as a little-endian u32, checked before anything gets allocated for it
+			let size = bytes.first_chunk::<4>().map_or(0, |x| u32::from_le_bytes(*x) as usize);
+			if size > limit { return Err(SocketError::FrameTooBig { size, limit }); }
+			lz4_flex::decompress_size_prepended(&bytes).map_err(|e| SocketError::Decode(format!("lz4: {e}")))
+		},
+		x => Err(SocketError::Decode(format!("unsupported compression {x}, is its feature enabled?"))),
+	}
+}
+
+/// Collects chunks until a message is complete.
+#[derive(Debug, Default)]
+pub(super) struct Reassembly {
+	buffer: Vec<u8>,
+	// header of the first chunk of the message being collected
+	compression: Option<u8>,
+	// the rest of a message that went over the limit, ignored up to its last chunk
+	skipping: bool,
+}
+
+impl Reassembly {
+	/// The whole message once `frame` was its last chunk.
+	pub(super) fn push(&mut self, frame: &[u8], chunking: &Chunking) -> Result<Option<Vec<u8>>, SocketError> {
+		let Some((&header, chunk)) = frame.split_first() else { return Err(SocketError::Decode("frame without a chunk header".to_owned())); };
+		if self.skipping {
+			self.skipping = header & FINAL == 0;
+			return Ok(None);
+		}
+		let compression = header & COMPRESSION_MASK;
+		if self.compression.is_some_and(|x| x != compression) {
+			self.clear();
+			return Err(SocketError::Decode("chunks of one message disagree on compression".to_owned()));
+		}
+		self.compression = Some(compression);
+
+		let size = self.buffer.len() + chunk.len();
+		if size > chunking.max_message_size {
+			self.clear();
+			self.skipping = header & FINAL == 0;
+			return Err(SocketError::FrameTooBig { size, limit: chunking.max_message_size });
+		}
+		self.buffer.extend_from_slice(chunk);
+		if header & FINAL == 0 { return Ok(None); }
+
+		let bytes = std::mem::take(&mut self.buffer);
+		self.clear();
+		decompress(compression, bytes, chunking.max_message_size).map(Some)
+	}
+
+	/// Drop a partial message, e.g. when the connection it was coming in on closes.
+	pub(super) fn clear(&mut self) {
+		self.buffer.clear();
+		self.compression = None;
+		self.skipping = false;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn reassemble(frames: &[Vec<u8>], chunking: &Chunking) -> Vec<Result<Option<Vec<u8>>, SocketError>> {
+		let mut reassembly = Reassembly::default();
+		frames.iter().map(|x| reassembly.push(x, chunking)).collect()
+	}
+
+	#[test]
+	fn round_trip() {
+		let chunking = Chunking::new(4);
+		let message = (0..10).collect::<Vec<u8>>();
+		let frames = chunking.split(message.clone());
+		assert_eq!(frames.len(), 4);
+		assert!(frames.iter().all(|x| x.len() <= 4));
+		assert_eq!(reassemble(&frames, &chunking), [Ok(None), Ok(None), Ok(None), Ok(Some(message))]);
+		assert_eq!(reassemble(&chunking.split(Vec::new()), &chunking), [Ok(Some(Vec::new()))]);
+	}
+
+	#[cfg(feature = "deflate")]
+	#[test]
+	fn deflate() {
+		let chunking = Chunking::new(16).compression(Compression::Deflate);
+		let message = vec![7; 1000];
+		let frames = chunking.split(message.clone());
+		assert_eq!(reassemble(&frames, &chunking).pop(), Some(Ok(Some(message))));
+	}
+
+	#[cfg(feature = "lz4")]
+	#[test]
+	fn lz4() {
+		let chunking = Chunking::new(16).compression(Compression::Lz4);
+		let message = vec![7; 1000];
+		let frames = chunking.split(message.clone());
+		assert_eq!(reassemble(&frames, &chunking).pop(), Some(Ok(Some(message))));
+	}
+
+	#[test]
+	fn over_the_limit() {
+		let chunking = Chunking::new(4).max_message_size(5);
+		let mut frames = chunking.split(vec![1; 9]);
+		frames.extend(chunking.split(vec![2; 3]));
+		// the rest of the oversized message is skipped, the next one comes through
+		assert_eq!(reassemble(&frames, &chunking), [
+			Ok(None),
+			Err(SocketError::FrameTooBig { size: 6, limit: 5 }),
+			Ok(None),
+			Ok(Some(vec![2; 3])),
+		]);
+	}
+
+	#[test]
+	fn malformed() {
+		let chunking = Chunking::default();
+		assert!(matches!(reassemble(&[Vec::new()], &chunking)[..], [Err(SocketError::Decode(_))]));
+		// compression changing halfway through a message
+		assert!(matches!(reassemble(&[vec![0, 1], vec![FINAL | 1, 2]], &chunking)[..], [Ok(None), Err(SocketError::Decode(_))]));
+	}
+}
@@ -0,0 +1,53 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Wire format used by [`Socket`](super::Socket) to turn messages into frames and back.
+///
+/// Methods are generic so that the same codec handles both `In` and `Out`.
+pub trait Codec: 'static {
+	/// Whether encoded messages are valid UTF-8 and should go out as text frames rather than binary ones.
+	const TEXT: bool;
+
+	fn encode<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>>;
+	fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T>;
+}
+
+/// The default codec, raw `postcard` bytes in binary frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+impl Codec for Postcard {
+	const TEXT: bool = false;
+
+	fn encode<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>> { Ok(postcard::to_stdvec(msg)?) }
+	fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> { Ok(postcard::from_bytes(bytes)?) }
+}
+
+/// `serde_json` in text frames.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+	const TEXT: bool = true;
+
+	fn encode<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>> { Ok(serde_json::to_vec(msg)?) }
+	fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> { Ok(serde_json::from_slice(bytes)?) }
+}
+
+/// CBOR via `ciborium` in binary frames.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+	const TEXT: bool = false;
+
+	fn encode<T: Serialize>(msg: &T) -> anyhow::Result<Vec<u8>> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(msg, &mut buf)?;
+		Ok(buf)
+	}
+	fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> { Ok(ciborium::from_reader(bytes)?) }
+}
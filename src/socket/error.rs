@@ -0,0 +1,20 @@
+use std::time::Duration;
+use super::{OutboxFull, SocketDropped, VersionMismatch};
+
+/// Everything that can go wrong with a [`Socket`](super::Socket).
+///
+/// Returned from sending, and reported to [`SocketBuilder::on_error`](super::SocketBuilder::on_error) when it happens in the background.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SocketError {
+	#[error("Failed to connect: '{0}'.")] Connect(String),
+	#[error("Failed to get credentials: '{0}'.")] Credentials(String),
+	#[error("Failed to encode message: '{0}'.")] Encode(String),
+	#[error("Failed to decode message: '{0}'.")] Decode(String),
+	#[error("Failed to send message: '{0}'.")] Send(String),
+	#[error("Received a frame of {size} bytes, over the limit of {limit}.")] FrameTooBig { size: usize, limit: usize },
+	#[error("Handshake failed: '{0}'.")] Handshake(String),
+	#[error("No traffic for {0:?}.")] HeartbeatTimeout(Duration),
+	#[error(transparent)] VersionMismatch(#[from] VersionMismatch),
+	#[error(transparent)] OutboxFull(#[from] OutboxFull),
+	#[error(transparent)] Dropped(#[from] SocketDropped),
+}
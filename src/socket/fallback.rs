@@ -0,0 +1,98 @@
+use std::{cell::Cell, rc::Rc, time::Duration};
+use super::{Connection, Frame, Sse, Transport, TransportEvents, WebSocket};
+
+/// Connects through `primary` until it fails `after` times in a row without ever opening, then through `secondary` for good,
+/// e.g. to get past proxies that kill websockets with [`Fallback::websocket_or_sse`].
+/// The server has to serve both, see [`Sse`] for what that takes.
+///
+/// Timers and the clock come from `primary`.
+#[derive(Clone)]
+pub struct Fallback<A: Transport, B: Transport> {
+	primary: A,
+	secondary: B,
+	after: u32,
+	state: Rc<FallbackState>,
+}
+
+#[derive(Default)]
+struct FallbackState {
+	// connections through `primary` that closed before opening, reset whenever one does open
+	failures: Cell<u32>,
+	fallen_back: Cell<bool>,
+}
+
+impl<A: Transport, B: Transport> Fallback<A, B> {
+	pub fn new(primary: A, secondary: B, after: u32) -> Self { Self { primary, secondary, after, state: Rc::default() } }
+
+	pub fn is_fallen_back(&self) -> bool { self.state.fallen_back.get() }
+
+	fn failed(state: &FallbackState, after: u32) {
+		let failures = state.failures.get() + 1;
+		state.failures.set(failures);
+		if failures >= after && !state.fallen_back.replace(true) { log::warn!("{failures} failed connects in a row, falling back"); }
+	}
+}
+
+impl Fallback<WebSocket, Sse> {
+	pub fn websocket_or_sse(after: u32) -> Self { Self::new(WebSocket, Sse, after) }
+}
+
+impl<A: Transport, B: Transport> Transport for Fallback<A, B> {
+	type Connection = FallbackConnection<A::Connection, B::Connection>;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<Self::Connection> {
+		if self.state.fallen_back.get() { return self.secondary.connect(url, events).map(FallbackConnection::Secondary); }
+
+		let opened = Rc::new(Cell::new(false));
+		let after = self.after;
+		let wrapped = TransportEvents {
+			on_open: Rc::new({ let events = events.clone(); let state = Rc::clone(&self.state); let opened = Rc::clone(&opened); move || {
+				opened.set(true);
+				state.failures.set(0);
+				events.open();
+			} }),
+			on_message: Rc::new({ let events = events.clone(); move |bytes| events.message(bytes) }),
+			on_close: Rc::new({ let state = Rc::clone(&self.state); move |code, reason| {
+				if !opened.get() { Self::failed(&state, after); }
+				events.close(code, reason);
+			} }),
+		};
+
+		let res = self.primary.connect(url, wrapped).map(FallbackConnection::Primary);
+		if res.is_err() { Self::failed(&self.state, after); }
+		res
+	}
+
+	fn now(&self) -> f64 { self.primary.now() }
+	fn random(&self) -> f64 { self.primary.random() }
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<A, B> + 'static { self.primary.sleep(duration) }
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) { self.primary.spawn(future) }
+}
+
+pub enum FallbackConnection<A: Connection, B: Connection> {
+	Primary(A),
+	Secondary(B),
+}
+
+impl<A: Connection, B: Connection> Connection for FallbackConnection<A, B> {
+	fn is_open(&self) -> bool {
+		match self {
+			Self::Primary(x) => x.is_open(),
+			Self::Secondary(x) => x.is_open(),
+		}
+	}
+
+	fn send(&self, frame: Frame) -> anyhow::Result<()> {
+		match self {
+			Self::Primary(x) => x.send(frame),
+			Self::Secondary(x) => x.send(frame),
+		}
+	}
+
+	fn close(&self, code: u16, reason: &str) {
+		match self {
+			Self::Primary(x) => x.close(code, reason),
+			Self::Secondary(x) => x.close(code, reason),
+		}
+	}
+}
@@ -0,0 +1,55 @@
+use std::{any::{Any, TypeId}, cell::RefCell, collections::HashMap, rc::{Rc, Weak}};
+use serde::Serialize;
+use super::{Codec, ConnectionState, Inner, Postcard, SendStatus, Socket, SocketError, Transport, WebSocket};
+
+/// A cloneable reference to a [`Socket`] that doesn't keep it alive.
+///
+/// Like `Socket` itself it's `!Send`, the browser only ever runs it on the main thread.
+/// Use [`register`] to keep a socket around globally.
+pub struct SocketHandle<Out, C: Codec = Postcard, T: Transport = WebSocket> {
+	inner: Weak<Inner<Out, T>>,
+	_codec: std::marker::PhantomData<C>,
+}
+
+impl<Out, C: Codec, T: Transport> Clone for SocketHandle<Out, C, T> {
+	fn clone(&self) -> Self { Self { inner: Weak::clone(&self.inner), _codec: std::marker::PhantomData } }
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Socket was dropped.")]
+pub struct SocketDropped;
+
+impl<Out: Serialize + 'static, C: Codec, T: Transport> Socket<Out, C, T> {
+	pub fn handle(&self) -> SocketHandle<Out, C, T> { SocketHandle { inner: Rc::downgrade(&self.inner), _codec: std::marker::PhantomData } }
+}
+
+impl<Out: Serialize + 'static, C: Codec, T: Transport> SocketHandle<Out, C, T> {
+	pub fn upgrade(&self) -> Option<Socket<Out, C, T>> { self.inner.upgrade().map(Socket::from_inner) }
+
+	/// Fails with [`SocketError::Dropped`] if the socket is gone.
+	pub fn send(&self, msg: Out) -> Result<SendStatus, SocketError> { self.upgrade().ok_or(SocketDropped)?.send(msg) }
+
+	pub fn state(&self) -> Option<ConnectionState> { self.upgrade().map(|x| x.state()) }
+}
+
+thread_local! {
+	static REGISTRY: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Keeps `socket` alive for the rest of the program (or until [`unregister`]), one per `Out`/`C`/`T` combination.
+/// A previously registered socket of the same type is dropped and so disconnected.
+pub fn register<Out: Serialize + 'static, C: Codec, T: Transport>(socket: Socket<Out, C, T>) -> SocketHandle<Out, C, T> {
+	let handle = socket.handle();
+	let previous = REGISTRY.with_borrow_mut(|x| x.insert(TypeId::of::<Socket<Out, C, T>>(), Box::new(socket)));
+	// dropped outside of the borrow in case closing it ends up touching the registry
+	drop(previous);
+	handle
+}
+
+pub fn registered<Out: Serialize + 'static, C: Codec, T: Transport>() -> Option<SocketHandle<Out, C, T>> {
+	REGISTRY.with_borrow(|x| x.get(&TypeId::of::<Socket<Out, C, T>>()).and_then(|x| x.downcast_ref::<Socket<Out, C, T>>()).map(Socket::handle))
+}
+
+pub fn unregister<Out: Serialize + 'static, C: Codec, T: Transport>() -> Option<Socket<Out, C, T>> {
+	REGISTRY.with_borrow_mut(|x| x.remove(&TypeId::of::<Socket<Out, C, T>>())).and_then(|x| x.downcast().ok()).map(|x| *x)
+}
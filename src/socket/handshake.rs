@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// First frame both sides send when the socket has a protocol version set, see [`SocketBuilder::protocol_version`](super::SocketBuilder::protocol_version).
+///
+/// Encoded with the socket's codec, like every other message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handshake {
+	/// Sent by the client as soon as the connection opens.
+	Hello { version: u32 },
+	/// The server speaks the client's version, regular messages follow.
+	Accepted,
+	/// The server doesn't speak the client's version and is about to close the connection.
+	Rejected { supported: Vec<u32> },
+}
+
+/// The server turned down the socket's protocol version, most likely because it was deployed since the page was loaded.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("Server does not speak protocol version {client}, it supports {server:?}.")]
+pub struct VersionMismatch {
+	pub client: u32,
+	/// Empty if the server didn't answer with a handshake at all.
+	pub server: Vec<u32>,
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+/// Application-level keepalive for [`Socket`](super::Socket), see [`SocketBuilder::heartbeat`](super::SocketBuilder::heartbeat).
+///
+/// Pings are empty frames and the server is expected to echo them back as they are, which `SocketServer` does with `ServerOptions::heartbeat`.
+/// Any inbound traffic counts as a sign of life, not just the echoes.
+///
+/// Every empty frame coming in is taken for an echo, so messages that encode to nothing, such as `()`, unit structs or empty tuples with postcard,
+/// never reach the socket's handler while heartbeats are on. Enable [`Chunking`](super::Chunking) on both ends to send those, its frames are never empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+	/// How often to ping.
+	pub interval: Duration,
+	/// How long the connection may stay silent before it's considered dead and closed.
+	pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+	fn default() -> Self { Self { interval: Duration::from_secs(20), timeout: Duration::from_secs(45) } }
+}
+
+impl Heartbeat {
+	pub fn new(interval: Duration, timeout: Duration) -> Self { Self { interval, timeout } }
+}
@@ -0,0 +1,242 @@
+use std::{cell::Cell, rc::{Rc, Weak}, time::Duration};
+use futures::{StreamExt, channel::mpsc};
+use wasm_bindgen_futures::{JsFuture, js_sys};
+use hobo::prelude::*;
+use super::{Connection, Frame, Transport, TransportEvents, WebSocket};
+
+/// A [`Transport`] over plain HTTP for networks that don't let websockets through: server-sent events in, `POST` requests out.
+///
+/// The socket's `ws://` or `wss://` url is used as `http://` or `https://` with a `session` query parameter added,
+/// which is the same for the event stream and every request of one connection so the server can tell which belong together.
+/// It's 32 random hex digits from `crypto.getRandomValues`, so it can't be guessed to send frames into someone else's session.
+/// Outgoing frames are `POST`ed one at a time and in order, the body being the frame as it is.
+///
+/// Every incoming event is one frame, its data starting with a letter that says what it is:
+/// `b` followed by base64 for binary frames, `t` followed by text for text frames,
+/// or `c` followed by a close code, a space and a reason, for the server to close the connection with.
+/// Heartbeat echoes are then just `b`.
+///
+/// # Server side
+///
+/// `SocketServer` only speaks websockets, the backend has to serve this format itself:
+/// * `GET` with a new `session` opens an event stream for it, every event's data being one frame as above, `b` using standard base64 with padding.
+/// * `POST` with that `session` is one frame from the client: the raw bytes for binary frames, UTF-8 for text frames.
+///   Anything but a 2xx response closes the connection on the client.
+/// * The session ends when the event stream goes away, the client never reuses it.
+///
+/// Everything on top, the codec, handshake, chunking and heartbeats, works the same as over a websocket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sse;
+
+/// Like [`Sse`], but the server is polled with `GET` requests with a `poll` query parameter instead of an event stream.
+///
+/// Each response holds any number of events, one per line, and is followed right away by the next poll.
+/// The connection counts as open once the first poll comes back, so the server should answer that one without waiting for anything to send.
+/// The session ends once the client stops polling, which the server has to notice by a timeout of its own. Sending works as with [`Sse`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongPoll;
+
+pub struct HttpConnection {
+	session: Rc<Session>,
+	inbound: Inbound,
+}
+
+enum Inbound {
+	Sse {
+		source: web_sys::EventSource,
+		_onopen: Closure<dyn FnMut(web_sys::Event)>,
+		_onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+		_onerror: Closure<dyn FnMut(web_sys::Event)>,
+	},
+	Poll(futures::future::AbortHandle),
+}
+
+struct Session {
+	url: String,
+	events: TransportEvents,
+	open: Cell<bool>,
+	// closed or dropped, no more events are delivered
+	closed: Cell<bool>,
+	outbound: mpsc::UnboundedSender<Frame>,
+}
+
+impl Session {
+	fn new(url: &str, events: TransportEvents) -> Rc<Self> {
+		let mut bytes = [0u8; 16];
+		crate::window().crypto().expect("no crypto").get_random_values_with_u8_array(&mut bytes).expect("failed to get random values");
+		let session = bytes.iter().map(|x| format!("{x:02x}")).collect::<String>();
+		let url = match url.split_once("://") {
+			Some(("ws", rest)) => format!("http://{rest}"),
+			Some(("wss", rest)) => format!("https://{rest}"),
+			_ => url.to_owned(),
+		};
+		let url = format!("{url}{}session={session}", if url.contains('?') { '&' } else { '?' });
+
+		let (tx, mut rx) = mpsc::unbounded::<Frame>();
+		let this = Rc::new(Self { url, events, open: Cell::new(false), closed: Cell::new(false), outbound: tx });
+
+		// one request at a time, otherwise they could arrive out of order
+		let weak = Rc::downgrade(&this);
+		wasm_bindgen_futures::spawn_local(async move {
+			while let Some(frame) = rx.next().await {
+				let Some(url) = weak.upgrade().map(|x| x.url.clone()) else { return; };
+				let init = web_sys::RequestInit::new();
+				init.set_method("POST");
+				init.set_body(&match frame {
+					Frame::Binary(x) => js_sys::Uint8Array::from(x.as_slice()).into(),
+					Frame::Text(x) => JsValue::from_str(&x),
+				});
+				if let Err(e) = fetch(&url, &init).await {
+					if let Some(this) = weak.upgrade() { this.fail(1006, format!("failed to send: {e}")); }
+					return;
+				}
+			}
+		});
+
+		this
+	}
+
+	fn opened(&self) {
+		if self.closed.get() || self.open.replace(true) { return; }
+		self.events.open();
+	}
+
+	fn deliver(&self, data: &str) {
+		if self.closed.get() { return; }
+		match data.split_at_checked(1) {
+			Some(("b", rest)) => match crate::window().atob(rest) {
+				// each char of a decoded "binary string" is one byte
+				Ok(x) => self.events.message(x.chars().map(|c| c as u8).collect()),
+				Err(e) => log::error!("Error decoding server event: {e:?}"),
+			},
+			Some(("t", rest)) => self.events.message(rest.as_bytes().to_vec()),
+			Some(("c", rest)) => {
+				let (code, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+				self.fail(code.parse().unwrap_or(1006), reason.to_owned());
+			},
+			_ => log::error!("unexpected server event: '{data}'"),
+		}
+	}
+
+	fn fail(&self, code: u16, reason: String) {
+		if self.closed.replace(true) { return; }
+		self.open.set(false);
+		self.outbound.close_channel();
+		self.events.close(code, reason);
+	}
+}
+
+async fn fetch(url: &str, init: &web_sys::RequestInit) -> Result<web_sys::Response, String> {
+	let response = JsFuture::from(crate::window().fetch_with_str_and_init(url, init)).await.map_err(|e| format!("{e:?}"))?;
+	let response = response.unchecked_into::<web_sys::Response>();
+	if !response.ok() { return Err(format!("{} {}", response.status(), response.status_text())); }
+	Ok(response)
+}
+
+async fn poll(url: &str) -> Result<String, String> {
+	let init = web_sys::RequestInit::new();
+	init.set_method("GET");
+	let response = fetch(url, &init).await?;
+	let text = JsFuture::from(response.text().map_err(|e| format!("{e:?}"))?).await.map_err(|e| format!("{e:?}"))?;
+	Ok(text.as_string().unwrap_or_default())
+}
+
+impl Transport for Sse {
+	type Connection = HttpConnection;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<HttpConnection> {
+		let session = Session::new(url, events);
+		let source = web_sys::EventSource::new(&session.url).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+
+		let onopen = Closure::<dyn FnMut(web_sys::Event)>::new({ let session = Rc::downgrade(&session); move |_: web_sys::Event| {
+			if let Some(session) = session.upgrade() { session.opened(); }
+		} });
+		let onmessage = Closure::<dyn FnMut(web_sys::MessageEvent)>::new({ let session = Rc::downgrade(&session); move |e: web_sys::MessageEvent| {
+			let Some(session) = session.upgrade() else { return; };
+			session.deliver(&e.data().as_string().unwrap_or_default());
+		} });
+		// `EventSource` would retry on its own, but reconnecting is up to the socket
+		let onerror = Closure::<dyn FnMut(web_sys::Event)>::new({ let session = Rc::downgrade(&session); let source = source.clone(); move |_: web_sys::Event| {
+			source.close();
+			if let Some(session) = session.upgrade() { session.fail(1006, String::new()); }
+		} });
+
+		source.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+		source.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+		source.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+		Ok(HttpConnection { session, inbound: Inbound::Sse { source, _onopen: onopen, _onmessage: onmessage, _onerror: onerror } })
+	}
+
+	fn now(&self) -> f64 { WebSocket.now() }
+	fn random(&self) -> f64 { WebSocket.random() }
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<> + 'static { WebSocket.sleep(duration) }
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) { WebSocket.spawn(future) }
+}
+
+impl Transport for LongPoll {
+	type Connection = HttpConnection;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<HttpConnection> {
+		let session = Session::new(url, events);
+
+		let weak: Weak<Session> = Rc::downgrade(&session);
+		let (task, handle) = futures::future::abortable(async move {
+			loop {
+				let Some(url) = weak.upgrade().map(|x| format!("{}&poll", x.url)) else { return; };
+				let body = poll(&url).await;
+				let Some(session) = weak.upgrade() else { return; };
+				let body = match body {
+					Ok(x) => x,
+					Err(e) => { session.fail(1006, e); return; },
+				};
+
+				session.opened();
+				for line in body.lines().filter(|x| !x.is_empty()) { session.deliver(line); }
+				if session.closed.get() { return; }
+			}
+		});
+		wasm_bindgen_futures::spawn_local(async move { task.await.ok(); });
+
+		Ok(HttpConnection { session, inbound: Inbound::Poll(handle) })
+	}
+
+	fn now(&self) -> f64 { WebSocket.now() }
+	fn random(&self) -> f64 { WebSocket.random() }
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<> + 'static { WebSocket.sleep(duration) }
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) { WebSocket.spawn(future) }
+}
+
+impl HttpConnection {
+	fn stop(&self) {
+		match &self.inbound {
+			Inbound::Sse { source, .. } => source.close(),
+			Inbound::Poll(handle) => handle.abort(),
+		}
+	}
+}
+
+impl Connection for HttpConnection {
+	fn is_open(&self) -> bool { self.session.open.get() && !self.session.closed.get() }
+
+	fn send(&self, frame: Frame) -> anyhow::Result<()> {
+		anyhow::ensure!(self.is_open(), "connection is not open");
+		self.session.outbound.unbounded_send(frame).map_err(|_| anyhow::anyhow!("connection is closed"))
+	}
+
+	/// Only stops listening, the server finds out when the event stream or poll goes away.
+	fn close(&self, code: u16, reason: &str) {
+		self.stop();
+		let session = Rc::clone(&self.session);
+		let reason = reason.to_owned();
+		wasm_bindgen_futures::spawn_local(async move { session.fail(code, reason); });
+	}
+}
+
+impl Drop for HttpConnection {
+	fn drop(&mut self) {
+		self.session.closed.set(true);
+		self.session.outbound.close_channel();
+		self.stop();
+	}
+}
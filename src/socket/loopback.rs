@@ -0,0 +1,414 @@
+use std::{cell::{Cell, RefCell}, collections::VecDeque, rc::{Rc, Weak}, time::Duration};
+use futures::{channel::oneshot, executor::{LocalPool, LocalSpawner}, task::LocalSpawnExt};
+use super::{Connection, Frame, Transport, TransportEvents};
+
+/// In-memory [`Transport`] with a manual clock and executor, for testing socket logic without a browser.
+///
+/// Every connection the socket makes shows up as a [`LoopbackPeer`] that plays the server's side.
+/// Nothing happens on its own: spawned tasks only run in [`Loopback::run_until_stalled`] and time only passes in [`Loopback::advance`].
+#[derive(Clone)]
+pub struct Loopback(Rc<LoopbackState>);
+
+struct LoopbackState {
+	now: Cell<f64>,
+	timers: RefCell<Vec<(f64, oneshot::Sender<()>)>>,
+	pool: RefCell<LocalPool>,
+	spawner: LocalSpawner,
+	peers: RefCell<Vec<LoopbackPeer>>,
+	failing_connects: Cell<u32>,
+	random: Cell<f64>,
+}
+
+impl Default for Loopback {
+	fn default() -> Self {
+		let pool = LocalPool::new();
+		Self(Rc::new(LoopbackState {
+			now: Cell::new(0.),
+			timers: RefCell::new(Vec::new()),
+			spawner: pool.spawner(),
+			pool: RefCell::new(pool),
+			peers: RefCell::new(Vec::new()),
+			failing_connects: Cell::new(0),
+			random: Cell::new(0.5),
+		}))
+	}
+}
+
+impl Loopback {
+	pub fn new() -> Self { Self::default() }
+
+	/// Run spawned tasks until none of them can make progress.
+	pub fn run_until_stalled(&self) { self.0.pool.borrow_mut().run_until_stalled(); }
+
+	/// Move the clock forward, firing timers in order as their time comes.
+	pub fn advance(&self, duration: Duration) {
+		let target = self.0.now.get() + duration.as_secs_f64() * 1000.;
+		self.run_until_stalled();
+		loop {
+			let due = {
+				let mut timers = self.0.timers.borrow_mut();
+				let Some(i) = timers.iter().enumerate().filter(|(_, (at, _))| *at <= target).min_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b)).map(|(i, _)| i) else { break; };
+				timers.swap_remove(i)
+			};
+			self.0.now.set(self.0.now.get().max(due.0));
+			due.1.send(()).ok();
+			self.run_until_stalled();
+		}
+		self.0.now.set(target);
+	}
+
+	/// Make the next `n` connection attempts fail right away.
+	pub fn fail_next_connects(&self, n: u32) { self.0.failing_connects.set(n); }
+
+	/// What `Transport::random` returns, 0.5 by default which makes reconnect delays come out without jitter.
+	pub fn set_random(&self, x: f64) { self.0.random.set(x); }
+
+	/// Every connection made so far, oldest first.
+	pub fn peers(&self) -> Vec<LoopbackPeer> { self.0.peers.borrow().clone() }
+	pub fn last_peer(&self) -> Option<LoopbackPeer> { self.0.peers.borrow().last().cloned() }
+}
+
+impl Transport for Loopback {
+	type Connection = LoopbackConnection;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<LoopbackConnection> {
+		let failing = self.0.failing_connects.get();
+		if failing > 0 {
+			self.0.failing_connects.set(failing - 1);
+			anyhow::bail!("loopback connect failure");
+		}
+
+		let peer = LoopbackPeer(Rc::new(PeerState {
+			url: url.to_owned(),
+			events,
+			loopback: Rc::downgrade(&self.0),
+			open: Cell::new(false),
+			closed: RefCell::new(None),
+			dropped: Cell::new(false),
+			received: RefCell::new(VecDeque::new()),
+		}));
+		self.0.peers.borrow_mut().push(peer.clone());
+		Ok(LoopbackConnection(peer))
+	}
+
+	fn now(&self) -> f64 { self.0.now.get() }
+	fn random(&self) -> f64 { self.0.random.get() }
+
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<> + 'static {
+		let (tx, rx) = oneshot::channel();
+		self.0.timers.borrow_mut().push((self.0.now.get() + duration.as_secs_f64() * 1000., tx));
+		async move { rx.await.ok(); }
+	}
+
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+		if let Err(e) = self.0.spawner.spawn_local(future) { log::error!("failed to spawn on loopback: {e:?}"); }
+	}
+}
+
+/// The server's end of one [`Loopback`] connection.
+#[derive(Clone)]
+pub struct LoopbackPeer(Rc<PeerState>);
+
+struct PeerState {
+	url: String,
+	events: TransportEvents,
+	loopback: Weak<LoopbackState>,
+	open: Cell<bool>,
+	closed: RefCell<Option<(u16, String)>>,
+	// the socket let go of the connection, no more events are delivered
+	dropped: Cell<bool>,
+	received: RefCell<VecDeque<Frame>>,
+}
+
+impl LoopbackPeer {
+	pub fn url(&self) -> &str { &self.0.url }
+	pub fn is_open(&self) -> bool { self.0.open.get() && self.0.closed.borrow().is_none() && !self.0.dropped.get() }
+	/// Close code and reason, whichever side closed the connection.
+	pub fn closed(&self) -> Option<(u16, String)> { self.0.closed.borrow().clone() }
+
+	fn live(&self) -> bool { !self.0.dropped.get() && self.0.closed.borrow().is_none() }
+
+	/// Finish the handshake.
+	pub fn accept(&self) {
+		if !self.live() { return; }
+		self.0.open.set(true);
+		self.0.events.open();
+	}
+
+	pub fn send(&self, frame: Frame) {
+		if !self.is_open() { return; }
+		self.0.events.message(frame.into_bytes());
+	}
+
+	/// Deliver `frame` once the loopback clock has moved `delay` forward.
+	pub fn send_after(&self, delay: Duration, frame: Frame) {
+		let Some(loopback) = self.0.loopback.upgrade().map(Loopback) else { return; };
+		let sleep = loopback.sleep(delay);
+		let this = self.clone();
+		loopback.spawn(async move { sleep.await; this.send(frame); });
+	}
+
+	/// Close from the server's side.
+	pub fn close(&self, code: u16, reason: &str) {
+		if !self.live() { return; }
+		*self.0.closed.borrow_mut() = Some((code, reason.to_owned()));
+		self.0.events.close(code, reason.to_owned());
+	}
+
+	/// Drop the connection without a close frame, same as a network error or a refused connection.
+	pub fn fail(&self) { self.close(1006, ""); }
+
+	/// Like [`close`](Self::close), but the socket only hears about it once spawned tasks run,
+	/// the way a browser websocket is already closed for a while before its `onclose` fires.
+	pub fn close_later(&self, code: u16, reason: &str) {
+		if !self.live() { return; }
+		*self.0.closed.borrow_mut() = Some((code, reason.to_owned()));
+		let Some(loopback) = self.0.loopback.upgrade().map(Loopback) else { return; };
+		let this = self.clone();
+		let reason = reason.to_owned();
+		loopback.spawn(async move { if !this.0.dropped.get() { this.0.events.close(code, reason); } });
+	}
+
+	/// Everything the socket sent since the last call, oldest first.
+	pub fn received(&self) -> Vec<Frame> { self.0.received.borrow_mut().drain(..).collect() }
+}
+
+pub struct LoopbackConnection(LoopbackPeer);
+
+impl Connection for LoopbackConnection {
+	fn is_open(&self) -> bool { self.0.is_open() }
+
+	fn send(&self, frame: Frame) -> anyhow::Result<()> {
+		anyhow::ensure!(self.0.is_open(), "loopback connection is not open");
+		self.0.0.received.borrow_mut().push_back(frame);
+		Ok(())
+	}
+
+	fn close(&self, code: u16, reason: &str) { self.0.close_later(code, reason); }
+}
+
+impl Drop for LoopbackConnection {
+	fn drop(&mut self) { self.0.0.dropped.set(true); }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::socket::{ConnectionState, Credentials, Heartbeat, OutboxFull, Overflow, Postcard, RpcRequest, RpcResponse, RpcSocket, SendOptions, SendStatus, Socket, SocketBuilder, SocketError};
+
+	type TestSocket = Socket<u32, Postcard, Loopback>;
+	type TestBuilder = SocketBuilder<u32, Postcard, Loopback>;
+	type Received = Rc<RefCell<Vec<u32>>>;
+	type Errors = Rc<RefCell<Vec<SocketError>>>;
+
+	fn binary(x: &impl serde::Serialize) -> Frame { Frame::Binary(postcard::to_stdvec(x).unwrap()) }
+
+	fn build(loopback: &Loopback, f: impl FnOnce(TestBuilder) -> TestBuilder) -> (TestSocket, Received, Errors) {
+		let received = Received::default();
+		let errors = Errors::default();
+		let socket = f(Socket::builder("ws://test").transport(loopback.clone()))
+			.on_error({ let errors = Rc::clone(&errors); move |e| errors.borrow_mut().push(e.clone()) })
+			.build(|_| {}, { let received = Rc::clone(&received); move |_, msg: u32| received.borrow_mut().push(msg) });
+		loopback.run_until_stalled();
+		(socket, received, errors)
+	}
+
+	#[test]
+	fn connect_and_exchange() {
+		let loopback = Loopback::new();
+		let (socket, received, errors) = build(&loopback, |x| x);
+		let peer = loopback.last_peer().unwrap();
+		assert_eq!(peer.url(), "ws://test");
+		assert_eq!(socket.state(), ConnectionState::Connecting { attempt: 0 });
+
+		peer.accept();
+		assert!(socket.state().is_open());
+		assert_eq!(socket.send(1), Ok(SendStatus::Sent));
+		assert_eq!(peer.received(), [binary(&1u32)]);
+
+		peer.send(binary(&2u32));
+		assert_eq!(*received.borrow(), [2]);
+		assert!(errors.borrow().is_empty());
+	}
+
+	#[test]
+	fn buffers_until_open() {
+		let loopback = Loopback::new();
+		let (socket, _, _) = build(&loopback, |x| x);
+		assert_eq!(socket.send(1), Ok(SendStatus::Buffered));
+		assert_eq!(socket.send(2), Ok(SendStatus::Buffered));
+
+		let peer = loopback.last_peer().unwrap();
+		assert!(peer.received().is_empty());
+		peer.accept();
+		assert_eq!(peer.received(), [binary(&1u32), binary(&2u32)]);
+	}
+
+	#[test]
+	fn outbox_overflow_and_coalescing() {
+		let loopback = Loopback::new();
+		let (socket, _, _) = build(&loopback, |x| x.outbox(2, Overflow::Error));
+		assert_eq!(socket.send_with(1, SendOptions::default().coalesce("a")), Ok(SendStatus::Buffered));
+		assert_eq!(socket.send(2), Ok(SendStatus::Buffered));
+		assert_eq!(socket.send(3), Err(SocketError::OutboxFull(OutboxFull)));
+		assert_eq!(socket.send_with(4, SendOptions::default().coalesce("a")), Ok(SendStatus::Buffered));
+
+		let peer = loopback.last_peer().unwrap();
+		peer.accept();
+		assert_eq!(peer.received(), [binary(&4u32), binary(&2u32)]);
+	}
+
+	#[test]
+	fn reconnect_delay() {
+		let loopback = Loopback::new();
+		let (socket, _, _) = build(&loopback, |x| x);
+		loopback.last_peer().unwrap().accept();
+		loopback.last_peer().unwrap().fail();
+		assert!(matches!(socket.state(), ConnectionState::Reconnecting { code: 1006, attempt: 1, .. }));
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(1)));
+
+		loopback.advance(Duration::from_millis(999));
+		assert_eq!(loopback.peers().len(), 1);
+		loopback.advance(Duration::from_millis(1));
+		assert_eq!(loopback.peers().len(), 2);
+		assert_eq!(socket.state(), ConnectionState::Connecting { attempt: 1 });
+
+		// backs off further while attempts keep failing
+		loopback.last_peer().unwrap().fail();
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(2)));
+		loopback.fail_next_connects(1);
+		loopback.advance(Duration::from_secs(2));
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(4)));
+		loopback.advance(Duration::from_secs(4));
+		assert_eq!(loopback.peers().len(), 3);
+
+		// and starts over once one opens
+		loopback.last_peer().unwrap().accept();
+		loopback.last_peer().unwrap().fail();
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(1)));
+	}
+
+	#[test]
+	fn auth_expired() {
+		let loopback = Loopback::new();
+		let token = Rc::new(Cell::new(0u32));
+		let (socket, _, _) = build(&loopback, |x| x
+			.credentials({ let token = Rc::clone(&token); move || std::future::ready(Ok(Credentials::default().auth(token.get()))) })
+			.auth_expired([4401], { let token = Rc::clone(&token); move || { token.set(token.get() + 1); std::future::ready(()) } }),
+		);
+		let reject = || {
+			let peer = loopback.last_peer().unwrap();
+			peer.accept();
+			peer.close(4401, "auth expired");
+			loopback.run_until_stalled();
+			peer
+		};
+
+		// the first rejection refreshes and reconnects right away
+		assert_eq!(reject().received(), [binary(&0u32)]);
+		assert_eq!(loopback.peers().len(), 2);
+		assert_eq!(socket.state(), ConnectionState::Connecting { attempt: 0 });
+
+		// a server that keeps accepting the connection and turning down the auth frame gets the usual backoff
+		assert_eq!(reject().received(), [binary(&1u32)]);
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(1)));
+		loopback.advance(Duration::from_secs(1));
+		assert_eq!(reject().received(), [binary(&2u32)]);
+		assert_eq!(socket.retry_in(), Some(Duration::from_secs(2)));
+		loopback.advance(Duration::from_secs(2));
+		assert_eq!(loopback.peers().len(), 4);
+		assert_eq!(token.get(), 3);
+
+		// once a connection got something through, the next rejection is retried right away again
+		let peer = loopback.last_peer().unwrap();
+		peer.accept();
+		peer.send(binary(&5u32));
+		peer.close(4401, "auth expired");
+		loopback.run_until_stalled();
+		assert_eq!(loopback.peers().len(), 5);
+		assert_eq!(socket.state(), ConnectionState::Connecting { attempt: 0 });
+	}
+
+	#[test]
+	fn heartbeat_timeout() {
+		let loopback = Loopback::new();
+		let (socket, _, errors) = build(&loopback, |x| x.heartbeat(Heartbeat::new(Duration::from_secs(1), Duration::from_secs(3))));
+		let peer = loopback.last_peer().unwrap();
+		peer.accept();
+
+		loopback.advance(Duration::from_secs(1));
+		assert_eq!(peer.received(), [Frame::Binary(Vec::new())]);
+		// an echo keeps it alive
+		peer.send(Frame::Binary(Vec::new()));
+		loopback.advance(Duration::from_secs(3));
+		assert!(peer.is_open());
+
+		loopback.advance(Duration::from_secs(1));
+		assert_eq!(peer.closed().map(|x| x.0), Some(4000));
+		assert_eq!(*errors.borrow(), [SocketError::HeartbeatTimeout(Duration::from_secs(3))]);
+		assert!(matches!(socket.state(), ConnectionState::Reconnecting { code: 4000, .. }));
+	}
+
+	#[test]
+	fn decode_errors() {
+		let loopback = Loopback::new();
+		let (_socket, received, errors) = build(&loopback, |x| x);
+		let peer = loopback.last_peer().unwrap();
+		peer.accept();
+
+		peer.send(Frame::Binary(vec![0xff; 6]));
+		peer.send(binary(&5u32));
+		assert_eq!(*received.borrow(), [5]);
+		assert!(matches!(errors.borrow()[..], [SocketError::Decode(_)]));
+		assert!(peer.is_open());
+	}
+
+	#[test]
+	fn rpc_round_trip() {
+		let loopback = Loopback::new();
+		let rpc = Rc::new(RpcSocket::<u32, String>::builder("ws://test").transport(loopback.clone()).build());
+		let result = Rc::new(RefCell::new(None));
+		loopback.spawn({ let rpc = Rc::clone(&rpc); let result = Rc::clone(&result); async move { *result.borrow_mut() = Some(rpc.call(7).await); } });
+		loopback.run_until_stalled();
+
+		let peer = loopback.last_peer().unwrap();
+		peer.accept();
+		loopback.run_until_stalled();
+		let [Frame::Binary(request)] = &peer.received()[..] else { panic!("expected one request") };
+		let request = postcard::from_bytes::<RpcRequest<u32>>(request).unwrap();
+		assert_eq!(request.body, 7);
+
+		peer.send(binary(&RpcResponse { id: request.id, body: "seven".to_owned() }));
+		loopback.run_until_stalled();
+		assert_eq!(result.borrow_mut().take(), Some(Ok("seven".to_owned())));
+	}
+
+	#[test]
+	fn rpc_bypasses_outbox() {
+		for retry in [false, true] {
+			let loopback = Loopback::new();
+			let rpc = RpcSocket::<u32, String>::builder("ws://test").transport(loopback.clone()).retry_on_reconnect(retry).build();
+			loopback.run_until_stalled();
+			loopback.last_peer().unwrap().accept();
+
+			// the connection is gone but the socket doesn't know yet
+			loopback.last_peer().unwrap().close_later(1006, "");
+			let mut call = Box::pin(rpc.call(7));
+			let mut cx = std::task::Context::from_waker(futures::task::noop_waker_ref());
+			assert!(call.as_mut().poll(&mut cx).is_pending());
+			loopback.run_until_stalled();
+			assert!(call.as_mut().poll(&mut cx).is_pending());
+
+			loopback.advance(Duration::from_secs(1));
+			let peer = loopback.last_peer().unwrap();
+			peer.accept();
+			// once, by the rpc socket rather than its outbox as well
+			let [Frame::Binary(request)] = &peer.received()[..] else { panic!("expected one request") };
+			let request = postcard::from_bytes::<RpcRequest<u32>>(request).unwrap();
+			assert_eq!(request.body, 7);
+
+			peer.send(binary(&RpcResponse { id: request.id, body: "seven".to_owned() }));
+			assert_eq!(call.as_mut().poll(&mut cx), std::task::Poll::Ready(Ok("seven".to_owned())));
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+/// What to do when a message is sent while the outbox is already at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+	/// Evict the oldest message of the lowest priority, as long as it's not more important than the new one.
+	#[default]
+	DropOldest,
+	/// Discard the message being sent.
+	DropNewest,
+	/// Discard the message being sent and return [`OutboxFull`] from `send`.
+	Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+	Low,
+	#[default]
+	Normal,
+	High,
+}
+
+/// Per-message options for [`Socket::send_with`](super::Socket::send_with).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SendOptions {
+	/// Higher priority messages are flushed first and evicted last.
+	pub priority: Priority,
+	/// A buffered message with the same key is replaced instead of queueing another one,
+	/// useful for state updates where only the latest one matters.
+	pub coalesce_key: Option<String>,
+}
+
+impl SendOptions {
+	#[must_use] pub fn priority(mut self, x: Priority) -> Self { self.priority = x; self }
+	#[must_use] pub fn coalesce(mut self, key: impl Into<String>) -> Self { self.coalesce_key = Some(key.into()); self }
+}
+
+/// What became of a message passed to [`Socket::send`](super::Socket::send).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+	Sent,
+	/// Waiting in the outbox for the socket to open.
+	Buffered,
+	/// The outbox was full and the message was discarded, see [`Overflow`].
+	Dropped,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Socket outbox is full, message was not buffered.")]
+pub struct OutboxFull;
+
+/// Messages waiting for the socket to open.
+pub(super) struct Outbox<Out> {
+	entries: VecDeque<(Out, SendOptions)>,
+	capacity: usize,
+	overflow: Overflow,
+	len: hobo::signal::Mutable<usize>,
+}
+
+impl<Out> Outbox<Out> {
+	pub(super) fn new(capacity: usize, overflow: Overflow) -> Self {
+		Self { entries: VecDeque::new(), capacity, overflow, len: hobo::signal::Mutable::new(0) }
+	}
+
+	pub(super) fn len_signal(&self) -> hobo::signal::MutableSignal<usize> { self.len.signal() }
+
+	pub(super) fn push(&mut self, msg: Out, options: SendOptions) -> Result<SendStatus, OutboxFull> {
+		if let Some(key) = &options.coalesce_key
+			&& let Some(entry) = self.entries.iter_mut().find(|(_, x)| x.coalesce_key.as_ref() == Some(key))
+		{
+			*entry = (msg, options);
+			return Ok(SendStatus::Buffered);
+		}
+
+		if self.entries.len() >= self.capacity {
+			match self.overflow {
+				Overflow::DropOldest => {
+					let victim = self.entries.iter()
+						.enumerate()
+						.filter(|(_, (_, x))| x.priority <= options.priority)
+						.min_by_key(|(_, (_, x))| x.priority)
+						.map(|(i, _)| i);
+					let Some(victim) = victim else {
+						log::warn!("socket outbox full of higher priority messages, dropping message");
+						return Ok(SendStatus::Dropped);
+					};
+					log::warn!("socket outbox full, dropping oldest message");
+					self.entries.remove(victim);
+				},
+				Overflow::DropNewest => { log::warn!("socket outbox full, dropping message"); return Ok(SendStatus::Dropped); },
+				Overflow::Error => return Err(OutboxFull),
+			}
+		}
+
+		self.entries.push_back((msg, options));
+		self.len.set(self.entries.len());
+		Ok(SendStatus::Buffered)
+	}
+
+	/// Everything buffered, highest priority first and oldest first within the same priority.
+	pub(super) fn drain(&mut self) -> Vec<(Out, SendOptions)> {
+		let mut entries = Vec::from(std::mem::take(&mut self.entries));
+		entries.sort_by_key(|(_, x)| std::cmp::Reverse(x.priority));
+		self.len.set(0);
+		entries
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn priority(x: Priority) -> SendOptions { SendOptions::default().priority(x) }
+	fn messages(outbox: &mut Outbox<u32>) -> Vec<u32> { outbox.drain().into_iter().map(|(x, _)| x).collect() }
+
+	#[test]
+	fn drains_by_priority_then_age() {
+		let mut outbox = Outbox::new(10, Overflow::DropOldest);
+		outbox.push(1, priority(Priority::Low)).unwrap();
+		outbox.push(2, priority(Priority::Normal)).unwrap();
+		outbox.push(3, priority(Priority::High)).unwrap();
+		outbox.push(4, priority(Priority::Normal)).unwrap();
+		assert_eq!(messages(&mut outbox), [3, 2, 4, 1]);
+		assert!(messages(&mut outbox).is_empty());
+	}
+
+	#[test]
+	fn coalescing() {
+		let mut outbox = Outbox::new(2, Overflow::Error);
+		outbox.push(1, SendOptions::default().coalesce("position")).unwrap();
+		outbox.push(2, SendOptions::default()).unwrap();
+		// replaces in place even when full
+		assert_eq!(outbox.push(3, SendOptions::default().coalesce("position")), Ok(SendStatus::Buffered));
+		assert_eq!(messages(&mut outbox), [3, 2]);
+	}
+
+	#[test]
+	fn drop_oldest() {
+		let mut outbox = Outbox::new(2, Overflow::DropOldest);
+		outbox.push(1, priority(Priority::High)).unwrap();
+		outbox.push(2, priority(Priority::Normal)).unwrap();
+		assert_eq!(outbox.push(3, priority(Priority::Normal)), Ok(SendStatus::Buffered));
+		assert_eq!(outbox.push(4, priority(Priority::Low)), Ok(SendStatus::Dropped));
+		assert_eq!(messages(&mut outbox), [1, 3]);
+	}
+
+	#[test]
+	fn drop_newest_and_error() {
+		let mut outbox = Outbox::new(1, Overflow::DropNewest);
+		outbox.push(1, SendOptions::default()).unwrap();
+		assert_eq!(outbox.push(2, SendOptions::default()), Ok(SendStatus::Dropped));
+		assert_eq!(messages(&mut outbox), [1]);
+
+		let mut outbox = Outbox::new(1, Overflow::Error);
+		outbox.push(1, SendOptions::default()).unwrap();
+		assert_eq!(outbox.push(2, SendOptions::default()), Err(OutboxFull));
+		assert_eq!(messages(&mut outbox), [1]);
+	}
+}
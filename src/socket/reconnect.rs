@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+/// How the delay between reconnect attempts grows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+	/// `initial_delay * factor^(attempt - 1)`
+	Exponential { factor: f64 },
+	/// `initial_delay + step * (attempt - 1)`
+	Linear { step: Duration },
+	/// Always `initial_delay`.
+	Fixed,
+	/// Don't reconnect at all.
+	Never,
+}
+
+/// Decides whether and when [`Socket`](super::Socket) reconnects after the connection is lost.
+///
+/// The default is exponential backoff starting at 1 second, doubling up to 10 minutes, with 20% jitter and no attempt limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+	pub backoff: Backoff,
+	pub initial_delay: Duration,
+	pub max_delay: Duration,
+	/// Fraction of the delay, in `0.0..=1.0`, that gets randomly added or subtracted so that clients don't all reconnect at once.
+	pub jitter: f64,
+	/// Give up after this many consecutive failed attempts, `None` to keep trying forever.
+	pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+	fn default() -> Self { Self::exponential(2.) }
+}
+
+impl ReconnectPolicy {
+	fn with_backoff(backoff: Backoff) -> Self { Self {
+		backoff,
+		initial_delay: Duration::from_secs(1),
+		max_delay: Duration::from_secs(60 * 10),
+		jitter: 0.2,
+		max_attempts: None,
+	} }
+
+	#[must_use] pub fn exponential(factor: f64) -> Self { Self::with_backoff(Backoff::Exponential { factor }) }
+	#[must_use] pub fn linear(step: Duration) -> Self { Self::with_backoff(Backoff::Linear { step }) }
+	#[must_use] pub fn fixed(delay: Duration) -> Self { Self::with_backoff(Backoff::Fixed).initial_delay(delay) }
+	#[must_use] pub fn never() -> Self { Self::with_backoff(Backoff::Never) }
+
+	#[must_use] pub fn initial_delay(mut self, x: Duration) -> Self { self.initial_delay = x; self }
+	#[must_use] pub fn max_delay(mut self, x: Duration) -> Self { self.max_delay = x; self }
+	#[must_use] pub fn jitter(mut self, x: f64) -> Self { self.jitter = x.clamp(0., 1.); self }
+	#[must_use] pub fn max_attempts(mut self, x: impl Into<Option<u32>>) -> Self { self.max_attempts = x.into(); self }
+
+	/// Delay before the given attempt (starting at 1), not including jitter.
+	/// `None` means the socket should stop reconnecting.
+	pub fn base_delay(&self, attempt: u32) -> Option<Duration> {
+		if self.max_attempts.is_some_and(|max| attempt > max) { return None; }
+		let n = attempt.saturating_sub(1);
+		let delay = match self.backoff {
+			Backoff::Exponential { factor } => {
+				let secs = self.initial_delay.as_secs_f64() * factor.powi(n.min(i32::MAX as u32) as i32);
+				Duration::from_secs_f64(secs.max(0.).min(self.max_delay.as_secs_f64()))
+			},
+			Backoff::Linear { step } => self.initial_delay.saturating_add(step.saturating_mul(n)),
+			Backoff::Fixed => self.initial_delay,
+			Backoff::Never => return None,
+		};
+		Some(delay.min(self.max_delay))
+	}
+
+	/// Delay before the given attempt (starting at 1) with jitter applied.
+	/// `random` is expected to be in `0.0..1.0`.
+	pub fn delay(&self, attempt: u32, random: f64) -> Option<Duration> {
+		let delay = self.base_delay(attempt)?;
+		let spread = self.jitter * (random * 2. - 1.);
+		let secs = delay.as_secs_f64() * (1. + spread);
+		Some(Duration::from_secs_f64(secs.max(0.).min(self.max_delay.as_secs_f64())))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn secs(x: f64) -> Option<Duration> { Some(Duration::from_secs_f64(x)) }
+
+	#[test]
+	fn exponential() {
+		let policy = ReconnectPolicy::exponential(2.).max_delay(Duration::from_secs(10));
+		assert_eq!(policy.delay(1, 0.5), secs(1.));
+		assert_eq!(policy.delay(2, 0.5), secs(2.));
+		assert_eq!(policy.delay(4, 0.5), secs(8.));
+		assert_eq!(policy.delay(5, 0.5), secs(10.));
+		assert_eq!(policy.delay(1000, 0.5), secs(10.));
+	}
+
+	#[test]
+	fn linear_and_fixed() {
+		let linear = ReconnectPolicy::linear(Duration::from_secs(3)).jitter(0.);
+		assert_eq!(linear.delay(1, 0.9), secs(1.));
+		assert_eq!(linear.delay(3, 0.9), secs(7.));
+
+		let fixed = ReconnectPolicy::fixed(Duration::from_secs(5)).jitter(0.);
+		assert_eq!(fixed.delay(1, 0.), secs(5.));
+		assert_eq!(fixed.delay(50, 0.), secs(5.));
+	}
+
+	#[test]
+	fn jitter() {
+		let policy = ReconnectPolicy::fixed(Duration::from_secs(10)).jitter(0.2);
+		assert_eq!(policy.delay(1, 0.), secs(8.));
+		assert_eq!(policy.delay(1, 0.5), secs(10.));
+		assert_eq!(policy.delay(1, 0.75), secs(11.));
+		// never past `max_delay`
+		assert_eq!(policy.clone().max_delay(Duration::from_secs(10)).delay(1, 0.99), secs(10.));
+	}
+
+	#[test]
+	fn giving_up() {
+		let policy = ReconnectPolicy::default().max_attempts(3);
+		assert!(policy.delay(3, 0.5).is_some());
+		assert_eq!(policy.delay(4, 0.5), None);
+		assert_eq!(ReconnectPolicy::never().delay(1, 0.5), None);
+	}
+}
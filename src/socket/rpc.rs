@@ -0,0 +1,177 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use super::{Codec, Postcard, Socket, SocketBuilder, SocketError, Transport, WebSocket};
+
+/// Envelope for calls going from [`RpcSocket`] to the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcRequest<T> {
+	pub id: u64,
+	pub body: T,
+}
+
+/// Envelope the server must answer an [`RpcRequest`] with, using the same `id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcResponse<T> {
+	pub id: u64,
+	pub body: T,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+	#[error("Call timed out.")] Timeout,
+	#[error("Connection dropped before a response arrived.")] Disconnected,
+	#[error("Failed to send call: '{0}'.")] Send(SocketError),
+}
+
+struct PendingCall<Req, Resp> {
+	request: Req,
+	// whether the request made it onto the current connection
+	sent: bool,
+	tx: oneshot::Sender<Result<Resp, RpcError>>,
+}
+
+struct Calls<Req, Resp> {
+	next_id: u64,
+	pending: HashMap<u64, PendingCall<Req, Resp>>,
+	retry_on_reconnect: bool,
+}
+
+impl<Req, Resp> Calls<Req, Resp> {
+	// calls whose future was dropped
+	fn remove_canceled(&mut self) { self.pending.retain(|_, call| !call.tx.is_canceled()); }
+}
+
+/// Request/response on top of [`Socket`], every call is matched to its response by a correlation id.
+///
+/// Calls made while the socket is not open wait for it to open. They never go through the socket's outbox, so each one is sent exactly once per connection.
+/// Calls that were already sent when the connection drops are rejected with [`RpcError::Disconnected`],
+/// or sent again after reconnecting if `retry_on_reconnect` is set.
+pub struct RpcSocket<Req, Resp, C: Codec = Postcard, T: Transport = WebSocket> {
+	socket: Socket<RpcRequest<Req>, C, T>,
+	calls: Rc<RefCell<Calls<Req, Resp>>>,
+	timeout: Option<Duration>,
+}
+
+pub struct RpcSocketBuilder<Req, Resp, C: Codec = Postcard, T: Transport = WebSocket> {
+	socket: SocketBuilder<RpcRequest<Req>, C, T>,
+	timeout: Option<Duration>,
+	retry_on_reconnect: bool,
+	_pd: std::marker::PhantomData<Resp>,
+}
+
+impl<Req, Resp, C, T> RpcSocketBuilder<Req, Resp, C, T> where
+	Req: Serialize + Clone + 'static,
+	Resp: DeserializeOwned + 'static,
+	C: Codec,
+	T: Transport,
+{
+	/// Configure the underlying socket, e.g. its reconnect policy.
+	#[must_use] pub fn socket(mut self, f: impl FnOnce(SocketBuilder<RpcRequest<Req>, C, T>) -> SocketBuilder<RpcRequest<Req>, C, T>) -> Self { self.socket = f(self.socket); self }
+	pub fn transport<NewT: Transport>(self, transport: NewT) -> RpcSocketBuilder<Req, Resp, C, NewT> {
+		RpcSocketBuilder { socket: self.socket.transport(transport), timeout: self.timeout, retry_on_reconnect: self.retry_on_reconnect, _pd: std::marker::PhantomData }
+	}
+	/// Default timeout for [`RpcSocket::call`], 30 seconds unless set.
+	#[must_use] pub fn timeout(mut self, x: impl Into<Option<Duration>>) -> Self { self.timeout = x.into(); self }
+	#[must_use] pub fn retry_on_reconnect(mut self, x: bool) -> Self { self.retry_on_reconnect = x; self }
+
+	pub fn build(self) -> RpcSocket<Req, Resp, C, T> {
+		let calls = Rc::new(RefCell::new(Calls::<Req, Resp> { next_id: 0, pending: HashMap::new(), retry_on_reconnect: self.retry_on_reconnect }));
+
+		let socket = self.socket.build_with(
+			{ let calls = Rc::downgrade(&calls); move |socket: &Socket<RpcRequest<Req>, C, T>| {
+				let Some(calls) = calls.upgrade() else { return; };
+				let mut calls = calls.borrow_mut();
+				calls.remove_canceled();
+				let mut failed = Vec::new();
+				for (&id, call) in calls.pending.iter_mut().filter(|(_, call)| !call.sent) {
+					match socket.send_now(&RpcRequest { id, body: call.request.clone() }) {
+						Ok(sent) => call.sent = sent,
+						Err(e) => failed.push((id, e)),
+					}
+				}
+				for (id, e) in failed {
+					if let Some(call) = calls.pending.remove(&id) { call.tx.send(Err(RpcError::Send(e))).ok(); }
+				}
+			} },
+			{ let calls = Rc::downgrade(&calls); move |_: &Socket<RpcRequest<Req>, C, T>, RpcResponse { id, body }: RpcResponse<Resp>| {
+				let Some(calls) = calls.upgrade() else { return; };
+				let Some(call) = calls.borrow_mut().pending.remove(&id) else { log::warn!("response for unknown or timed out call {id}"); return; };
+				call.tx.send(Ok(body)).ok();
+			} },
+			{ let calls = Rc::downgrade(&calls); move |_: &Socket<RpcRequest<Req>, C, T>| {
+				let Some(calls) = calls.upgrade() else { return; };
+				let mut calls = calls.borrow_mut();
+				calls.remove_canceled();
+				if calls.retry_on_reconnect {
+					for call in calls.pending.values_mut() { call.sent = false; }
+				} else {
+					let dropped = calls.pending.extract_if(|_, call| call.sent).collect::<Vec<_>>();
+					for (_, call) in dropped { call.tx.send(Err(RpcError::Disconnected)).ok(); }
+				}
+			} },
+		);
+
+		RpcSocket { socket, calls, timeout: self.timeout }
+	}
+}
+
+impl<Req, Resp, C> RpcSocket<Req, Resp, C> where
+	Req: Serialize + Clone + 'static,
+	Resp: DeserializeOwned + 'static,
+	C: Codec,
+{
+	pub fn builder(url: &str) -> RpcSocketBuilder<Req, Resp, C> {
+		RpcSocketBuilder { socket: Socket::builder(url), timeout: Some(Duration::from_secs(30)), retry_on_reconnect: false, _pd: std::marker::PhantomData }
+	}
+
+	pub fn new(url: &str) -> Self { Self::builder(url).build() }
+}
+
+impl<Req, Resp, C, T> RpcSocket<Req, Resp, C, T> where
+	Req: Serialize + Clone + 'static,
+	Resp: DeserializeOwned + 'static,
+	C: Codec,
+	T: Transport,
+{
+	pub fn socket(&self) -> &Socket<RpcRequest<Req>, C, T> { &self.socket }
+
+	pub async fn call(&self, req: Req) -> Result<Resp, RpcError> { self.call_with_timeout(req, self.timeout).await }
+
+	pub async fn call_with_timeout(&self, req: Req, timeout: Option<Duration>) -> Result<Resp, RpcError> {
+		let (tx, rx) = oneshot::channel();
+		let id = {
+			let mut calls = self.calls.borrow_mut();
+			calls.remove_canceled();
+			let id = calls.next_id;
+			calls.next_id += 1;
+			calls.pending.insert(id, PendingCall { request: req.clone(), sent: false, tx });
+			id
+		};
+
+		match self.socket.send_now(&RpcRequest { id, body: req }) {
+			Err(e) => {
+				self.calls.borrow_mut().pending.remove(&id);
+				return Err(RpcError::Send(e));
+			},
+			// unsent ones go out along with the other unsent calls once the socket opens
+			Ok(sent) => if let Some(call) = self.calls.borrow_mut().pending.get_mut(&id) { call.sent = sent; },
+		}
+
+		let res = match timeout {
+			None => rx.await,
+			Some(timeout) => {
+				match futures::future::select(rx, std::pin::pin!(self.socket.inner.transport.sleep(timeout))).await {
+					futures::future::Either::Left((res, _)) => res,
+					futures::future::Either::Right(_) => {
+						self.calls.borrow_mut().pending.remove(&id);
+						return Err(RpcError::Timeout);
+					},
+				}
+			},
+		};
+
+		// the sender is only dropped without sending if the whole socket went away
+		res.unwrap_or(Err(RpcError::Disconnected))
+	}
+}
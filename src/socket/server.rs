@@ -0,0 +1,356 @@
+use std::{net::SocketAddr, pin::Pin, task::{Context, Poll}};
+use futures::{SinkExt, Stream, StreamExt, channel::mpsc};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio_tungstenite::tungstenite::{Message, protocol::{CloseFrame, frame::coding::CloseCode}};
+use super::{Chunking, Codec, HANDSHAKE_TIMEOUT, Handshake, Postcard, SocketStream, chunking::Reassembly};
+
+/// Native end of the protocol [`Socket`](super::Socket) speaks, for use on the backend behind the `server` feature.
+///
+/// Messages are frames holding nothing but the codec's encoding of one message, text frames for text codecs,
+/// unless [`Chunking`] is enabled in the [`ServerOptions`]. With [`ServerOptions::heartbeat`], empty frames are pings that get echoed back without reaching the connection's stream.
+/// Envelopes like [`RpcRequest`](super::RpcRequest) or [`ChannelFrame`](super::ChannelFrame) are plain messages,
+/// so an `RpcSocket<Req, Resp>` is served by a `ServerConnection<RpcRequest<Req>, RpcResponse<Resp>>`.
+///
+/// Websockets only, clients on the [`Sse`](super::Sse) or [`LongPoll`](super::LongPoll) transports need a backend that serves their format.
+pub struct SocketServer<C: Codec = Postcard> {
+	listener: TcpListener,
+	options: ServerOptions,
+	_codec: std::marker::PhantomData<C>,
+}
+
+/// Has to match how the clients' sockets are built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerOptions {
+	/// Expect a [`Handshake`] from every client, for sockets built with [`SocketBuilder::protocol_version`](super::SocketBuilder::protocol_version).
+	/// Clients with any other version are sent the list and disconnected.
+	pub protocol_versions: Option<Vec<u32>>,
+	/// For sockets built with [`SocketBuilder::chunking`](super::SocketBuilder::chunking).
+	pub chunking: Option<Chunking>,
+	/// Echo empty frames, for sockets built with [`SocketBuilder::heartbeat`](super::SocketBuilder::heartbeat).
+	/// Messages that encode to nothing, such as `()` with postcard, can't be told apart from pings then unless chunking is enabled too.
+	pub heartbeat: bool,
+}
+
+impl ServerOptions {
+	#[must_use] pub fn protocol_versions(mut self, supported: impl IntoIterator<Item = u32>) -> Self { self.protocol_versions = Some(supported.into_iter().collect()); self }
+	#[must_use] pub fn chunking(mut self, x: impl Into<Option<Chunking>>) -> Self { self.chunking = x.into(); self }
+	#[must_use] pub fn heartbeat(mut self, x: bool) -> Self { self.heartbeat = x; self }
+}
+
+impl<C: Codec> SocketServer<C> {
+	pub async fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+		Ok(Self { listener: TcpListener::bind(addr).await?, options: ServerOptions::default(), _codec: std::marker::PhantomData })
+	}
+
+	#[must_use] pub fn options(mut self, x: ServerOptions) -> Self { self.options = x; self }
+
+	/// Useful after binding to port 0.
+	pub fn local_addr(&self) -> std::io::Result<SocketAddr> { self.listener.local_addr() }
+
+	/// Wait for the next client to connect. Errors are the listener's, a client failing its handshakes only shows up in [`IncomingClient::upgrade`].
+	pub async fn accept(&self) -> std::io::Result<IncomingClient<C>> {
+		let (stream, peer_addr) = self.listener.accept().await?;
+		Ok(IncomingClient { stream, peer_addr, options: self.options.clone(), _codec: std::marker::PhantomData })
+	}
+}
+
+/// A client that connected to a [`SocketServer`] but hasn't been through the websocket and protocol handshakes yet.
+///
+/// Those take as long as the client does, so upgrade in a task of its own rather than in the accept loop:
+/// ```ignore
+/// loop {
+///     let client = server.accept().await?;
+///     tokio::spawn(async move {
+///         let connection = client.upgrade::<In, Out>().await?;
+///         // ...
+///     });
+/// }
+/// ```
+pub struct IncomingClient<C: Codec = Postcard> {
+	stream: TcpStream,
+	peer_addr: SocketAddr,
+	options: ServerOptions,
+	_codec: std::marker::PhantomData<C>,
+}
+
+impl<C: Codec> IncomingClient<C> {
+	pub fn peer_addr(&self) -> SocketAddr { self.peer_addr }
+
+	/// Complete the handshakes, a client that fails them shows up as an error.
+	pub async fn upgrade<In, Out>(self) -> anyhow::Result<ServerConnection<In, Out, C>> where
+		In: DeserializeOwned + Send + 'static,
+		Out: Serialize,
+	{
+		ServerConnection::accept_with(self.stream, &self.options).await
+	}
+}
+
+/// One client of a [`SocketServer`], a stream of the messages it sends.
+///
+/// The stream ends once the client disconnects. Messages that fail to decode are logged and skipped, same as on the client.
+/// Must be created within a tokio runtime, reading and writing happen in tasks of their own.
+pub struct ServerConnection<In, Out, C: Codec = Postcard> {
+	peer_addr: SocketAddr,
+	version: Option<u32>,
+	sender: ServerSender<Out, C>,
+	incoming: SocketStream<In>,
+}
+
+impl<In, Out, C> ServerConnection<In, Out, C> where
+	In: DeserializeOwned + Send + 'static,
+	Out: Serialize,
+	C: Codec,
+{
+	/// Complete the websocket handshake on an already accepted stream.
+	pub async fn accept(stream: TcpStream) -> anyhow::Result<Self> { Self::accept_with(stream, &ServerOptions::default()).await }
+
+	/// Like `accept`, a client that fails the [`Handshake`] shows up as an error.
+	/// Clients get as long as sockets wait for the server to answer their hello to get through both handshakes.
+	pub async fn accept_with(stream: TcpStream, options: &ServerOptions) -> anyhow::Result<Self> {
+		let peer_addr = stream.peer_addr()?;
+		let deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+		let timed_out = |_| anyhow::anyhow!("{peer_addr} didn't finish the handshake in {HANDSHAKE_TIMEOUT:?}");
+		let (mut ws_tx, mut ws_rx) = tokio::time::timeout_at(deadline, tokio_tungstenite::accept_async(stream)).await.map_err(timed_out)??.split();
+		let chunking = options.chunking;
+		let heartbeat = options.heartbeat;
+		let mut reassembly = Reassembly::default();
+
+		let version = if let Some(supported) = &options.protocol_versions {
+			let hello = tokio::time::timeout_at(deadline, async {
+				loop {
+					let msg = ws_rx.next().await.ok_or_else(|| anyhow::anyhow!("{peer_addr} disconnected during handshake"))??;
+					if matches!(msg, Message::Close(_)) { anyhow::bail!("{peer_addr} disconnected during handshake"); }
+					let Some(bytes) = frame_bytes(msg) else { continue; };
+					let bytes = match &chunking {
+						Some(chunking) => match reassembly.push(&bytes, chunking)? { Some(x) => x, None => continue },
+						None => bytes,
+					};
+					break C::decode::<Handshake>(&bytes);
+				}
+			}).await.map_err(timed_out)??;
+			let Handshake::Hello { version } = hello else { anyhow::bail!("expected hello from {peer_addr}, got {hello:?}") };
+			if !supported.contains(&version) {
+				for msg in messages::<C>(&Handshake::Rejected { supported: supported.clone() }, chunking.as_ref())? { ws_tx.feed(msg).await?; }
+				ws_tx.send(Message::Close(Some(CloseFrame { code: CloseCode::from(4001), reason: "protocol version mismatch".into() }))).await?;
+				anyhow::bail!("{peer_addr} speaks unsupported protocol version {version}");
+			}
+			for msg in messages::<C>(&Handshake::Accepted, chunking.as_ref())? { ws_tx.feed(msg).await?; }
+			ws_tx.flush().await?;
+			Some(version)
+		} else { None };
+
+		let (out_tx, mut out_rx) = mpsc::unbounded::<Message>();
+		let (in_tx, in_rx) = mpsc::unbounded::<In>();
+
+		tokio::spawn(async move {
+			while let Some(msg) = out_rx.next().await {
+				let is_close = matches!(msg, Message::Close(_));
+				if let Err(e) = ws_tx.send(msg).await { log::warn!("failed to send to {peer_addr}: {e}"); break; }
+				if is_close { break; }
+			}
+			// also flushes tungstenite's reply if the client was the one closing
+			ws_tx.close().await.ok();
+		});
+
+		let echo = out_tx.clone();
+		tokio::spawn(async move {
+			while let Some(msg) = ws_rx.next().await {
+				let bytes = match msg {
+					Ok(Message::Close(_)) => break,
+					Ok(msg) => match frame_bytes(msg) { Some(x) => x, None => continue },
+					Err(e) => { log::warn!("connection to {peer_addr} failed: {e}"); break; },
+				};
+				if heartbeat && bytes.is_empty() {
+					echo.unbounded_send(if C::TEXT && chunking.is_none() { Message::text("") } else { Message::binary(Vec::new()) }).ok();
+					continue;
+				}
+				let bytes = match &chunking {
+					Some(chunking) => match reassembly.push(&bytes, chunking) {
+						Ok(Some(x)) => x,
+						Ok(None) => continue,
+						Err(e) => { log::error!("Error reassembling message from {peer_addr}: {e}"); continue; },
+					},
+					None => bytes,
+				};
+				match C::decode::<In>(&bytes) {
+					Ok(msg) => if in_tx.unbounded_send(msg).is_err() { break; },
+					Err(e) => log::error!("Error deserializing message from {peer_addr}: {e:?}"),
+				}
+			}
+			echo.close_channel();
+		});
+
+		Ok(Self { peer_addr, version, sender: ServerSender { tx: out_tx, chunking, _pd: std::marker::PhantomData }, incoming: SocketStream(in_rx) })
+	}
+
+	pub fn peer_addr(&self) -> SocketAddr { self.peer_addr }
+	/// The protocol version agreed on in the handshake, if there was one.
+	pub fn version(&self) -> Option<u32> { self.version }
+	pub fn sender(&self) -> &ServerSender<Out, C> { &self.sender }
+	pub fn send(&self, msg: Out) -> anyhow::Result<()> { self.sender.send(msg) }
+	pub fn close(&self, code: u16, reason: &str) { self.sender.close(code, reason) }
+
+	/// To read and write from different tasks.
+	pub fn into_split(self) -> (ServerSender<Out, C>, SocketStream<In>) { (self.sender, self.incoming) }
+}
+
+impl<In, Out, C: Codec> Stream for ServerConnection<In, Out, C> {
+	type Item = In;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<In>> { self.incoming.poll_next_unpin(cx) }
+}
+
+/// Sending half of a [`ServerConnection`], can be cloned and sent across threads.
+pub struct ServerSender<Out, C: Codec = Postcard> {
+	tx: mpsc::UnboundedSender<Message>,
+	chunking: Option<Chunking>,
+	_pd: std::marker::PhantomData<fn(Out, C)>,
+}
+
+impl<Out, C: Codec> Clone for ServerSender<Out, C> {
+	fn clone(&self) -> Self { Self { tx: self.tx.clone(), chunking: self.chunking, _pd: std::marker::PhantomData } }
+}
+
+impl<Out: Serialize, C: Codec> ServerSender<Out, C> {
+	pub fn send(&self, msg: Out) -> anyhow::Result<()> {
+		for msg in messages::<C>(&msg, self.chunking.as_ref())? {
+			self.tx.unbounded_send(msg).map_err(|_| anyhow::anyhow!("connection is closed"))?;
+		}
+		Ok(())
+	}
+
+	/// The client sees `code` and `reason` in its close event, and reconnects unless told not to by its reconnect policy.
+	pub fn close(&self, code: u16, reason: &str) {
+		self.tx.unbounded_send(Message::Close(Some(CloseFrame { code: CloseCode::from(code), reason: reason.into() }))).ok();
+	}
+
+	pub fn is_closed(&self) -> bool { self.tx.is_closed() }
+}
+
+fn messages<C: Codec>(msg: &impl Serialize, chunking: Option<&Chunking>) -> anyhow::Result<Vec<Message>> {
+	let bytes = C::encode(msg)?;
+	Ok(match chunking {
+		Some(chunking) => chunking.split(bytes).into_iter().map(Message::binary).collect(),
+		None if C::TEXT => vec![Message::text(String::from_utf8(bytes)?)],
+		None => vec![Message::binary(bytes)],
+	})
+}
+
+fn frame_bytes(msg: Message) -> Option<Vec<u8>> {
+	match msg {
+		Message::Binary(x) => Some(x.to_vec()),
+		Message::Text(x) => Some(x.as_bytes().to_vec()),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::net::TcpStream;
+	use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+	use super::*;
+
+	type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+	/// A server on a free port and a client connected to it, having sent `first` if there is one.
+	async fn connect<In, Out>(options: ServerOptions, first: Option<Message>) -> (anyhow::Result<ServerConnection<In, Out>>, Client) where
+		In: DeserializeOwned + Send + 'static,
+		Out: Serialize,
+	{
+		let server = SocketServer::bind("127.0.0.1:0").await.unwrap().options(options);
+		let url = format!("ws://{}", server.local_addr().unwrap());
+		tokio::join!(async { server.accept().await.unwrap().upgrade().await }, async move {
+			let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+			if let Some(first) = first { client.send(first).await.unwrap(); }
+			client
+		})
+	}
+
+	#[tokio::test]
+	async fn slow_client_does_not_block_others() {
+		let server = SocketServer::<Postcard>::bind("127.0.0.1:0").await.unwrap();
+		let addr = server.local_addr().unwrap();
+		// connects but never starts the websocket handshake
+		let _silent = TcpStream::connect(addr).await.unwrap();
+		let silent = server.accept().await.unwrap();
+
+		let (connection, client) = tokio::join!(async { server.accept().await.unwrap().upgrade::<u32, u32>().await }, tokio_tungstenite::connect_async(format!("ws://{addr}")));
+		connection.unwrap();
+		client.unwrap();
+		drop(silent);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn handshake_timeout() {
+		let options = ServerOptions::default().protocol_versions([1]);
+		// never says hello
+		let (connection, _client) = connect::<u32, u32>(options, None).await;
+		assert!(connection.err().unwrap().to_string().contains("didn't finish the handshake"));
+	}
+
+	async fn next(client: &mut Client) -> Message { client.next().await.expect("disconnected").unwrap() }
+
+	fn binary(x: &impl Serialize) -> Message { Message::binary(postcard::to_stdvec(x).unwrap()) }
+
+	#[tokio::test]
+	async fn handshake_heartbeat_and_messages() {
+		let options = ServerOptions::default().protocol_versions([1, 2]).heartbeat(true);
+		let (connection, mut client) = connect::<u32, String>(options, Some(binary(&Handshake::Hello { version: 2 }))).await;
+		let mut connection = connection.unwrap();
+		assert_eq!(connection.version(), Some(2));
+		assert_eq!(next(&mut client).await, binary(&Handshake::Accepted));
+
+		client.send(Message::binary(Vec::new())).await.unwrap();
+		assert_eq!(next(&mut client).await, Message::binary(Vec::new()));
+
+		client.send(binary(&5u32)).await.unwrap();
+		assert_eq!(connection.next().await, Some(5));
+		connection.send("five".to_owned()).unwrap();
+		assert_eq!(next(&mut client).await, binary(&"five"));
+
+		client.close(None).await.unwrap();
+		assert_eq!(connection.next().await, None);
+	}
+
+	#[tokio::test]
+	async fn version_mismatch() {
+		let options = ServerOptions::default().protocol_versions([2]);
+		let (connection, mut client) = connect::<u32, u32>(options, Some(binary(&Handshake::Hello { version: 1 }))).await;
+		assert!(connection.is_err());
+		assert_eq!(next(&mut client).await, binary(&Handshake::Rejected { supported: vec![2] }));
+		let Message::Close(Some(close)) = next(&mut client).await else { panic!("expected a close frame") };
+		assert_eq!(u16::from(close.code), 4001);
+	}
+
+	#[tokio::test]
+	async fn chunking() {
+		let chunking = Chunking::new(8);
+		let (connection, mut client) = connect::<String, String>(ServerOptions::default().chunking(chunking), None).await;
+		let mut connection = connection.unwrap();
+		let message = "a message that takes a few frames".to_owned();
+
+		for frame in chunking.split(postcard::to_stdvec(&message).unwrap()) { client.send(Message::binary(frame)).await.unwrap(); }
+		assert_eq!(connection.next().await.as_ref(), Some(&message));
+
+		connection.send(message.clone()).unwrap();
+		let mut reassembly = Reassembly::default();
+		let bytes = loop {
+			let Message::Binary(frame) = next(&mut client).await else { panic!("expected a binary frame") };
+			assert!(frame.len() <= 8);
+			if let Some(x) = reassembly.push(&frame, &chunking).unwrap() { break x; }
+		};
+		assert_eq!(postcard::from_bytes::<String>(&bytes).unwrap(), message);
+	}
+
+	#[tokio::test]
+	async fn empty_messages_without_heartbeat() {
+		let (connection, mut client) = connect::<(), ()>(ServerOptions::default(), None).await;
+		let mut connection = connection.unwrap();
+
+		client.send(Message::binary(Vec::new())).await.unwrap();
+		assert_eq!(connection.next().await, Some(()));
+		connection.send(()).unwrap();
+		assert_eq!(next(&mut client).await, Message::binary(Vec::new()));
+	}
+}
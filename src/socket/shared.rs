@@ -0,0 +1,241 @@
+use std::{cell::{Cell, RefCell}, rc::{Rc, Weak}, time::Duration};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen_futures::js_sys;
+use hobo::prelude::*;
+use super::{Connection, Frame, Transport, TransportEvents, WebSocket, WebSocketConnection};
+
+/// A [`Transport`] that shares one connection between all tabs using the same `name`.
+///
+/// The tab that holds the `name` web lock is the leader: it opens the real `WebSocket` and relays it over a `BroadcastChannel`.
+/// Everyone else gets a proxy connection, their sends are forwarded to the leader and they see everything the server sends.
+/// When the leader tab closes the lock passes to another tab, which then opens the real connection, and the sockets in every tab reconnect.
+///
+/// The server only ever sees one connection, so anything that relies on per-connection state doesn't mix with it,
+/// such as the version handshake or [`RpcSocket`](super::RpcSocket) calls from more than one tab.
+/// Leadership is held as long as this transport is used by a socket, so drop rather than close sockets that are no longer needed.
+#[derive(Clone)]
+pub struct SharedWebSocket(Rc<SharedState>);
+
+struct SharedState {
+	channel: web_sys::BroadcastChannel,
+	_onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+	leader: hobo::signal::Mutable<bool>,
+	// resolves the promise that keeps the lock held
+	release: RefCell<Option<js_sys::Function>>,
+	connection: RefCell<Weak<SharedConnectionState>>,
+}
+
+/// What goes over the `BroadcastChannel`, postcard encoded.
+#[derive(Debug, Serialize, Deserialize)]
+enum TabMessage {
+	/// A follower started connecting, the leader answers with `Opened` if its connection is already open.
+	Query,
+	/// From a follower, for the leader to send to the server.
+	Send(Frame),
+	Opened,
+	Message(Vec<u8>),
+	Closed { code: u16, reason: String },
+}
+
+#[wasm_bindgen]
+extern "C" {
+	// web-sys only has `LockManager` behind `web_sys_unstable_apis`
+	type LockManager;
+	#[wasm_bindgen(method)]
+	fn request(this: &LockManager, name: &str, callback: &JsValue) -> js_sys::Promise;
+}
+
+impl SharedWebSocket {
+	pub fn new(name: &str) -> anyhow::Result<Self> {
+		let channel = web_sys::BroadcastChannel::new(&format!("hobo-plus-socket:{name}")).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+		let locks = js_sys::Reflect::get(&crate::window().navigator(), &"locks".into()).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+		anyhow::ensure!(!locks.is_undefined(), "web locks are not supported");
+
+		let state = Rc::new_cyclic(|weak: &Weak<SharedState>| {
+			let onmessage = Closure::<dyn FnMut(web_sys::MessageEvent)>::new({ let weak = Weak::clone(weak); move |e: web_sys::MessageEvent| {
+				let Some(this) = weak.upgrade() else { return; };
+				match postcard::from_bytes::<TabMessage>(&js_sys::Uint8Array::new(&e.data()).to_vec()) {
+					Ok(msg) => this.received(msg),
+					Err(e) => log::error!("Error deserializing tab message: {e:?}"),
+				}
+			} });
+			channel.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+			SharedState {
+				channel,
+				_onmessage: onmessage,
+				leader: hobo::signal::Mutable::new(false),
+				release: RefCell::new(None),
+				connection: RefCell::new(Weak::new()),
+			}
+		});
+
+		let weak = Rc::downgrade(&state);
+		let granted = Closure::once_into_js(move |_: JsValue| -> js_sys::Promise {
+			let Some(this) = weak.upgrade() else { return js_sys::Promise::resolve(&JsValue::UNDEFINED); };
+			let held = js_sys::Promise::new(&mut |resolve, _| { *this.release.borrow_mut() = Some(resolve); });
+			this.became_leader();
+			held
+		});
+		// resolves once the lock is released, nothing to do then
+		let _ = locks.unchecked_into::<LockManager>().request(&format!("hobo-plus-socket:{name}"), &granted);
+
+		Ok(Self(state))
+	}
+
+	pub fn is_leader(&self) -> bool { self.0.leader.get() }
+	pub fn leader_signal(&self) -> impl hobo::signal::Signal<Item = bool> + 'static { self.0.leader.signal() }
+}
+
+impl SharedState {
+	fn post(&self, msg: &TabMessage) {
+		let bytes = match postcard::to_stdvec(msg) {
+			Ok(x) => x,
+			Err(e) => { log::error!("Error serializing tab message: {e:?}"); return; },
+		};
+		if let Err(e) = self.channel.post_message(&js_sys::Uint8Array::from(bytes.as_slice())) { log::warn!("failed to post tab message: {e:?}"); }
+	}
+
+	fn received(&self, msg: TabMessage) {
+		let Some(connection) = self.connection.borrow().upgrade() else { return; };
+		if connection.dead.get() { return; }
+
+		if let Some(ws) = connection.leading.borrow().as_ref() {
+			match msg {
+				TabMessage::Query if ws.is_open() => self.post(&TabMessage::Opened),
+				TabMessage::Send(frame) => if let Err(e) = ws.send(frame) { log::warn!("failed to forward message from another tab: {e:?}"); },
+				_ => {},
+			}
+			return;
+		}
+
+		// events may well drop the connection, so no borrows past this point
+		match msg {
+			TabMessage::Query | TabMessage::Send(_) => {},
+			TabMessage::Opened => if !connection.open.replace(true) { connection.events.open(); },
+			TabMessage::Message(bytes) => if connection.open.get() { connection.events.message(bytes); },
+			TabMessage::Closed { code, reason } => {
+				connection.dead.set(true);
+				connection.events.close(code, reason);
+			},
+		}
+	}
+
+	fn became_leader(self: &Rc<Self>) {
+		self.leader.set(true);
+		// a leader tab that's closed or reloaded never gets to say its connection is gone, so the other tabs would keep sending into the void
+		self.post(&TabMessage::Closed { code: 1001, reason: "leader tab went away".to_owned() });
+		let Some(connection) = self.connection.borrow().upgrade() else { return; };
+		if connection.dead.get() || connection.leading.borrow().is_some() { return; }
+
+		if connection.open.get() {
+			// the old leader's connection is gone, the socket has to go through reconnecting for this tab to open a new one
+			connection.dead.set(true);
+			connection.events.close(1001, "leader tab went away".to_owned());
+			return;
+		}
+
+		match self.lead(&connection.url, &connection.events) {
+			Ok(ws) => *connection.leading.borrow_mut() = Some(ws),
+			Err(e) => {
+				connection.dead.set(true);
+				connection.events.close(1006, format!("{e:?}"));
+			},
+		}
+	}
+
+	/// Open the real connection, relaying whatever happens to it to the other tabs.
+	fn lead(self: &Rc<Self>, url: &str, events: &TransportEvents) -> anyhow::Result<WebSocketConnection> {
+		let weak = Rc::downgrade(self);
+		let relay = move |msg: TabMessage| if let Some(this) = weak.upgrade() { this.post(&msg); };
+		WebSocket.connect(url, TransportEvents {
+			on_open: Rc::new({ let events = events.clone(); let relay = relay.clone(); move || { relay(TabMessage::Opened); events.open(); } }),
+			on_message: Rc::new({ let events = events.clone(); let relay = relay.clone(); move |bytes: Vec<u8>| { relay(TabMessage::Message(bytes.clone())); events.message(bytes); } }),
+			on_close: Rc::new({ let events = events.clone(); move |code, reason: String| { relay(TabMessage::Closed { code, reason: reason.clone() }); events.close(code, reason); } }),
+		})
+	}
+}
+
+impl Drop for SharedState {
+	fn drop(&mut self) {
+		self.channel.set_onmessage(None);
+		self.channel.close();
+		if let Some(release) = self.release.get_mut().take() { release.call0(&JsValue::UNDEFINED).ok(); }
+	}
+}
+
+impl Transport for SharedWebSocket {
+	type Connection = SharedConnection;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<SharedConnection> {
+		let leading = if self.0.leader.get() { Some(self.0.lead(url, &events)?) } else { None };
+		let is_follower = leading.is_none();
+		let connection = Rc::new(SharedConnectionState {
+			url: url.to_owned(),
+			events,
+			leading: RefCell::new(leading),
+			open: Cell::new(false),
+			dead: Cell::new(false),
+		});
+		*self.0.connection.borrow_mut() = Rc::downgrade(&connection);
+		if is_follower { self.0.post(&TabMessage::Query); }
+
+		Ok(SharedConnection { shared: Rc::downgrade(&self.0), state: connection })
+	}
+
+	fn now(&self) -> f64 { WebSocket.now() }
+	fn random(&self) -> f64 { WebSocket.random() }
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<> + 'static { WebSocket.sleep(duration) }
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) { WebSocket.spawn(future) }
+}
+
+/// Either the real connection or a proxy for the one in the leader tab, see [`SharedWebSocket`].
+pub struct SharedConnection {
+	shared: Weak<SharedState>,
+	state: Rc<SharedConnectionState>,
+}
+
+struct SharedConnectionState {
+	url: String,
+	events: TransportEvents,
+	leading: RefCell<Option<WebSocketConnection>>,
+	// whether the leader's connection is open, only meaningful while following
+	open: Cell<bool>,
+	// closed or dropped, no more events are delivered
+	dead: Cell<bool>,
+}
+
+impl Connection for SharedConnection {
+	fn is_open(&self) -> bool {
+		if let Some(ws) = self.state.leading.borrow().as_ref() { return ws.is_open(); }
+		self.state.open.get() && !self.state.dead.get()
+	}
+
+	fn send(&self, frame: Frame) -> anyhow::Result<()> {
+		if let Some(ws) = self.state.leading.borrow().as_ref() { return ws.send(frame); }
+		anyhow::ensure!(self.is_open(), "leader tab's connection is not open");
+		let shared = self.shared.upgrade().ok_or_else(|| anyhow::anyhow!("shared transport was dropped"))?;
+		shared.post(&TabMessage::Send(frame));
+		Ok(())
+	}
+
+	/// Only closes the real connection in the leader tab, a follower just stops listening.
+	fn close(&self, code: u16, reason: &str) {
+		if let Some(ws) = self.state.leading.borrow().as_ref() { ws.close(code, reason); return; }
+		if self.state.dead.replace(true) { return; }
+		let state = Rc::clone(&self.state);
+		let reason = reason.to_owned();
+		WebSocket.spawn(async move { state.events.close(code, reason); });
+	}
+}
+
+impl Drop for SharedConnection {
+	fn drop(&mut self) {
+		self.state.dead.set(true);
+		let Some(ws) = self.state.leading.borrow_mut().take() else { return; };
+		// closes the real connection without a close event, so the other tabs have to be told
+		if ws.is_open() && let Some(shared) = self.shared.upgrade() {
+			shared.post(&TabMessage::Closed { code: 1001, reason: "leader socket dropped".to_owned() });
+		}
+	}
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// Where a [`Socket`](super::Socket) is in its lifecycle, see [`Socket::state_signal`](super::Socket::state_signal).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+	/// Waiting for the connection to open, `attempt` is 0 for the very first connection.
+	Connecting { attempt: u32 },
+	Open,
+	Closing,
+	/// The connection was lost and the next attempt is scheduled.
+	Reconnecting {
+		code: u16,
+		reason: String,
+		attempt: u32,
+		/// Milliseconds on the socket transport's clock, see [`Transport::now`](super::Transport::now).
+		retry_at: f64,
+	},
+	/// The connection was lost and the socket is not going to reconnect on its own.
+	Closed { code: u16, reason: String },
+}
+
+impl Default for ConnectionState {
+	fn default() -> Self { Self::Connecting { attempt: 0 } }
+}
+
+impl ConnectionState {
+	pub fn is_open(&self) -> bool { matches!(self, Self::Open) }
+
+	/// Time left until the next reconnect attempt, if one is scheduled.
+	/// `now` has to come from the same transport, [`Socket::retry_in`](super::Socket::retry_in) takes care of that.
+	pub fn retry_in(&self, now: f64) -> Option<Duration> {
+		let Self::Reconnecting { retry_at, .. } = self else { return None; };
+		Some(Duration::from_secs_f64(((retry_at - now) / 1000.).max(0.)))
+	}
+}
@@ -0,0 +1,23 @@
+use std::{pin::Pin, task::{Context, Poll}};
+use futures::{Sink, Stream, StreamExt, channel::mpsc};
+use serde::Serialize;
+use super::{Codec, Socket, SocketError, Transport};
+
+/// Incoming messages of a socket created with [`SocketBuilder::build_stream`](super::SocketBuilder::build_stream).
+pub struct SocketStream<In>(pub(super) mpsc::UnboundedReceiver<In>);
+
+impl<In> Stream for SocketStream<In> {
+	type Item = In;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<In>> { self.0.poll_next_unpin(cx) }
+}
+
+/// Never applies backpressure, messages are buffered while the socket is not open same as with `send`.
+impl<Out: Serialize + 'static, C: Codec, T: Transport> Sink<Out> for Socket<Out, C, T> {
+	type Error = SocketError;
+
+	fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> { Poll::Ready(Ok(())) }
+	fn start_send(self: Pin<&mut Self>, msg: Out) -> Result<(), Self::Error> { self.send(msg).map(drop) }
+	fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> { Poll::Ready(Ok(())) }
+	fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> { Poll::Ready(Ok(())) }
+}
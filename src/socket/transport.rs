@@ -0,0 +1,59 @@
+use std::{rc::Rc, time::Duration};
+use serde::{Deserialize, Serialize};
+
+/// A single websocket message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Frame {
+	Binary(Vec<u8>),
+	Text(String),
+}
+
+impl Frame {
+	pub fn into_bytes(self) -> Vec<u8> {
+		match self {
+			Self::Binary(x) => x,
+			Self::Text(x) => x.into_bytes(),
+		}
+	}
+}
+
+/// Where [`Socket`](super::Socket) gets its connections, timers and clock from.
+///
+/// [`WebSocket`](super::WebSocket) is what's used in the browser, [`Loopback`](super::Loopback) runs anywhere and lets tests play the server.
+pub trait Transport: 'static {
+	type Connection: Connection;
+
+	/// Start connecting to `url`, reporting what happens to the connection through `events`.
+	/// Events must not be reported from within `connect` or any `Connection` method, only later on.
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<Self::Connection>;
+
+	/// Milliseconds, only ever compared to each other.
+	fn now(&self) -> f64;
+	/// In `0.0..1.0`.
+	fn random(&self) -> f64;
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<Self> + 'static;
+	fn spawn(&self, future: impl Future<Output = ()> + 'static);
+}
+
+/// One connection made by a [`Transport`]. Dropping it must stop it from reporting any more events.
+pub trait Connection: 'static {
+	fn is_open(&self) -> bool;
+	fn send(&self, frame: Frame) -> anyhow::Result<()>;
+	/// Must be followed by a close event, unless the connection is dropped first.
+	fn close(&self, code: u16, reason: &str);
+}
+
+/// Handed to [`Transport::connect`] for reporting on the connection.
+#[derive(Clone)]
+pub struct TransportEvents {
+	pub(super) on_open: Rc<dyn Fn()>,
+	pub(super) on_message: Rc<dyn Fn(Vec<u8>)>,
+	pub(super) on_close: Rc<dyn Fn(u16, String)>,
+}
+
+impl TransportEvents {
+	pub fn open(&self) { (self.on_open)() }
+	pub fn message(&self, bytes: Vec<u8>) { (self.on_message)(bytes) }
+	/// Also used for connections that fail to open at all, browsers report those with code 1006.
+	pub fn close(&self, code: u16, reason: String) { (self.on_close)(code, reason) }
+}
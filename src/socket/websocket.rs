@@ -0,0 +1,70 @@
+use std::time::Duration;
+use wasm_bindgen_futures::js_sys;
+use hobo::prelude::*;
+use super::{Connection, Frame, Transport, TransportEvents};
+
+/// The browser's `WebSocket`, the default [`Transport`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebSocket;
+
+pub struct WebSocketConnection {
+	ws: web_sys::WebSocket,
+	_onopen: Closure<dyn FnMut(web_sys::Event)>,
+	_onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+	_onclose: Closure<dyn FnMut(web_sys::CloseEvent)>,
+}
+
+impl Transport for WebSocket {
+	type Connection = WebSocketConnection;
+
+	fn connect(&self, url: &str, events: TransportEvents) -> anyhow::Result<WebSocketConnection> {
+		let ws = web_sys::WebSocket::new(url).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+
+		let onopen = Closure::<dyn FnMut(web_sys::Event)>::new({ let events = events.clone(); move |_: web_sys::Event| events.open() });
+		let onmessage = Closure::<dyn FnMut(web_sys::MessageEvent)>::new({ let events = events.clone(); move |e: web_sys::MessageEvent| {
+			let data = e.data();
+			events.message(match data.as_string() {
+				Some(text) => text.into_bytes(),
+				None => js_sys::Uint8Array::new(&data).to_vec(),
+			});
+		} });
+		let onclose = Closure::<dyn FnMut(web_sys::CloseEvent)>::new(move |e: web_sys::CloseEvent| events.close(e.code(), e.reason()));
+
+		ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+		ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+		ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+		ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+
+		Ok(WebSocketConnection { ws, _onopen: onopen, _onmessage: onmessage, _onclose: onclose })
+	}
+
+	fn now(&self) -> f64 { js_sys::Date::now() }
+	fn random(&self) -> f64 { js_sys::Math::random() }
+	fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + use<> + 'static { async move { async_timer::interval(duration).wait().await; } }
+	fn spawn(&self, future: impl Future<Output = ()> + 'static) { wasm_bindgen_futures::spawn_local(future); }
+}
+
+impl Connection for WebSocketConnection {
+	fn is_open(&self) -> bool { self.ws.ready_state() == web_sys::WebSocket::OPEN }
+
+	fn send(&self, frame: Frame) -> anyhow::Result<()> {
+		match frame {
+			Frame::Binary(x) => self.ws.send_with_u8_array(&x),
+			Frame::Text(x) => self.ws.send_with_str(&x),
+		}.map_err(|e| anyhow::anyhow!("{e:?}"))
+	}
+
+	fn close(&self, code: u16, reason: &str) {
+		if let Err(e) = self.ws.close_with_code_and_reason(code, reason) { log::warn!("failed to close socket: {e:?}"); }
+	}
+}
+
+impl Drop for WebSocketConnection {
+	fn drop(&mut self) {
+		self.ws.set_onopen(None);
+		self.ws.set_onmessage(None);
+		self.ws.set_onclose(None);
+		let state = self.ws.ready_state();
+		if state == web_sys::WebSocket::CONNECTING || state == web_sys::WebSocket::OPEN { self.ws.close_with_code(1000).ok(); }
+	}
+}